@@ -0,0 +1,222 @@
+use crate::requests::{redact, redact_headers, SentRequest};
+use crate::utils::write_atomic;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use color_eyre::eyre::{self, WrapErr};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Response, ResponseBuilderExt, Url};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Whether a cassette is being written to or served from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Make real requests and save every exchange.
+    Record,
+    /// Serve saved exchanges without touching the network.
+    Replay,
+}
+
+/// A request as it was sent over the wire.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub body: Option<String>,
+}
+
+/// A response as it was received, after following redirects.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// The body is base64 encoded because it isn't valid UTF-8.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub base64: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+/// A file of recorded HTTP exchanges that can be replayed later.
+///
+/// In record mode every exchange made by [`crate::requests::make_request`]
+/// is appended to the cassette and written out with [`Cassette::save`].
+/// In replay mode requests are answered from the cassette instead of the network,
+/// so the checker reaches the same verdicts it did when the cassette was recorded.
+#[derive(Debug)]
+pub struct Cassette {
+    mode: CassetteMode,
+    path: PathBuf,
+    /// Response bodies are recorded up to this many bytes.
+    max_body_size: usize,
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+impl Cassette {
+    /// Start an empty cassette that will be saved to `path`, keeping up to
+    /// `max_body_size` bytes of each response body.
+    #[must_use]
+    pub fn record(path: impl Into<PathBuf>, max_body_size: usize) -> Self {
+        Self {
+            mode: CassetteMode::Record,
+            path: path.into(),
+            max_body_size,
+            interactions: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Load a previously recorded cassette for replay.
+    pub fn replay(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).wrap_err_with(|| {
+            format!(
+                "Problem while attempting to read cassette '{}'",
+                path.display()
+            )
+        })?;
+        let interactions: Vec<Interaction> = serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("Problem parsing cassette '{}'", path.display()))?;
+
+        Ok(Self {
+            mode: CassetteMode::Replay,
+            path: path.to_path_buf(),
+            max_body_size: usize::MAX,
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    #[must_use]
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    /// Answer a request from the cassette.
+    ///
+    /// Requests are matched on method, URL and body; headers are ignored
+    /// because user agents are picked at random.
+    pub fn play(&self, request: &SentRequest, body: Option<&str>) -> color_eyre::Result<Response> {
+        let interactions = self.interactions.lock().expect("cassette lock poisoned");
+        let interaction = interactions
+            .iter()
+            .find(|interaction| {
+                interaction.request.method == request.method.as_str()
+                    && interaction.request.url == request.url
+                    && interaction.request.body.as_deref() == body
+            })
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "No recorded response for {} {} in cassette '{}'",
+                    request.method,
+                    request.url,
+                    self.path.display()
+                )
+            })?;
+
+        interaction.response.to_response()
+    }
+
+    /// Read the response, save the exchange and hand back an equivalent response.
+    pub async fn capture(
+        &self,
+        request: &SentRequest,
+        request_body: Option<&str>,
+        mut response: Response,
+        secrets: &[String],
+    ) -> color_eyre::Result<Response> {
+        let status = response.status().as_u16();
+        let url = redact(response.url().as_str(), secrets);
+        let headers = header_pairs(&redact_headers(response.headers(), secrets));
+        // one byte past the limit, so a replay sees the body was cut off too
+        let limit = self.max_body_size.saturating_add(1);
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            let take = chunk.len().min(limit - bytes.len());
+            bytes.extend_from_slice(&chunk[..take]);
+            if bytes.len() == limit {
+                break;
+            }
+        }
+        let (body, base64) = match std::str::from_utf8(&bytes) {
+            Ok(text) => (text.to_string(), false),
+            Err(_) => (BASE64.encode(&bytes), true),
+        };
+        let recorded = RecordedResponse {
+            status,
+            url,
+            headers,
+            body,
+            base64,
+        };
+        let replayed = recorded.to_response()?;
+
+        let interaction = Interaction {
+            request: RecordedRequest {
+                method: request.method.to_string(),
+                url: request.url.clone(),
+                headers: header_pairs(&request.headers),
+                body: request_body.map(str::to_string),
+            },
+            response: recorded,
+        };
+        self.interactions
+            .lock()
+            .expect("cassette lock poisoned")
+            .push(interaction);
+
+        Ok(replayed)
+    }
+
+    /// Write the recorded exchanges to the cassette file.
+    pub fn save(&self) -> color_eyre::Result<()> {
+        let interactions = self.interactions.lock().expect("cassette lock poisoned");
+        let json = serde_json::to_string_pretty(&*interactions)?;
+        write_atomic(&self.path, |file| file.write_all(json.as_bytes()))
+            .wrap_err_with(|| format!("Problem writing cassette '{}'", self.path.display()))?;
+
+        Ok(())
+    }
+}
+
+impl RecordedResponse {
+    fn to_response(&self) -> color_eyre::Result<Response> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            headers.append(name.parse::<HeaderName>()?, value.parse::<HeaderValue>()?);
+        }
+
+        let mut builder = http::Response::builder()
+            .status(self.status)
+            .url(Url::parse(&self.url)?);
+        if let Some(builder_headers) = builder.headers_mut() {
+            *builder_headers = headers;
+        }
+
+        let body = match self.base64 {
+            true => BASE64.decode(&self.body)?,
+            false => self.body.clone().into_bytes(),
+        };
+
+        Ok(Response::from(builder.body(body)?))
+    }
+}
+
+fn header_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
@@ -1,14 +1,31 @@
 use crate::{
+    cassette::Cassette,
+    confidence::{confidence, SiteReliability},
+    cookies::CookieJars,
+    credentials::Credentials,
+    dump::{DumpOptions, ResponseDump},
+    extract::{extract_metadata, Metadata},
     output::print_result,
-    query::{add_result_to_channel, QueryError, QueryResult, QueryStatus},
-    requests::RequestResult,
+    progress::Progress,
+    proxy_pool::{ProxyOutcome, ProxyPool, ProxySource},
+    query::{add_error_to_channel, add_result_to_channel, QueryError, QueryResult, QueryStatus},
+    requests::{read_body, redact, RequestResult, ResponseBody},
+    routing::{Route, Routing},
     sherlock_target_manifest::{ErrorType, TargetInfo},
-    waf::waf_hit,
+    tor::Tor,
+    transport::Transport,
+    user_agent::UserAgents,
+    waf::WafDatabase,
 };
 use color_eyre::eyre;
 use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::mpsc::channel;
+use tracing::{debug, warn};
+
+/// Bodies of non-2xx responses are read up to this many bytes to recognise
+/// WAF challenge pages, even when detection doesn't need the body.
+const WAF_BODY_LIMIT: usize = 64 * 1024;
 
 #[derive(Debug, Clone)]
 pub struct CheckOptions {
@@ -16,8 +33,33 @@ pub struct CheckOptions {
     pub proxy: Option<Arc<str>>,
     pub print_all: bool,
     pub print_found: bool,
-    pub dump_response: bool,
+    pub dump: Option<DumpOptions>,
     pub browse: bool,
+    pub progress: bool,
+    pub cassette: Option<Arc<Cassette>>,
+    /// Don't print claimed results with a lower confidence than this.
+    pub min_confidence: f32,
+    pub site_reliability: Option<Arc<SiteReliability>>,
+    /// Fingerprints of responses served by a WAF or bot challenge.
+    pub waf: Arc<WafDatabase>,
+    /// Stop reading response bodies after this many bytes.
+    pub max_body_size: usize,
+    /// Route every request over Tor instead of `proxy`.
+    pub tor: Option<Arc<Tor>>,
+    /// Spread requests across these proxies instead of using `proxy`.
+    pub proxy_pool: Option<Arc<ProxyPool>>,
+    /// Per-site rules that override the proxy, proxy pool and Tor.
+    pub routing: Option<Arc<Routing>>,
+    /// The browser profiles requests are sent as.
+    pub user_agents: Arc<UserAgents>,
+    /// Keep cookies per site across redirects and usernames.
+    pub cookies: Option<Arc<CookieJars>>,
+    /// Credentials the manifest can reference as `{credential.<kind>}`.
+    pub credentials: Arc<Credentials>,
+    /// HTTP version and TLS settings.
+    pub transport: Arc<Transport>,
+    /// Retry requests that failed this many times before giving up.
+    pub retries: u32,
 }
 
 pub async fn check_username(
@@ -30,8 +72,22 @@ pub async fn check_username(
         proxy,
         print_all,
         print_found,
-        dump_response,
+        dump,
         browse,
+        progress,
+        cassette,
+        min_confidence,
+        site_reliability,
+        waf,
+        max_body_size,
+        tor,
+        proxy_pool,
+        routing,
+        user_agents,
+        cookies,
+        credentials,
+        transport,
+        retries,
     } = options;
 
     let num_of_sites = site_data.len();
@@ -43,19 +99,64 @@ pub async fn check_username(
 
     // ping sites for username matches
     let username = Arc::from(username);
+    let mut num_of_checks = 0;
     for (site, info) in site_data.iter() {
+        let route = routing
+            .as_ref()
+            .and_then(|routing| routing.route(site, info, &username));
+        let proxy_source = match (route, tor, proxy_pool) {
+            (Some(Route::Skip), _, _) => {
+                debug!(site = %site, "skipped by routing rule");
+                continue;
+            }
+            // nothing goes around Tor, which is why `--tor` only allows skip rules
+            (_, Some(tor), _) => tor
+                .proxy_url(site)
+                .map(|url| ProxySource::Fixed(Some(Arc::from(url))))
+                .map_err(|e| QueryError::ProxyUnavailable(e.to_string())),
+            (Some(Route::Direct), None, _) => Ok(ProxySource::Fixed(None)),
+            (Some(Route::Proxy(proxy)), None, _) => {
+                Ok(ProxySource::Fixed(Some(Arc::from(proxy.as_str()))))
+            }
+            (None, None, Some(pool)) => Ok(ProxySource::Pool(Arc::clone(pool))),
+            (None, None, None) => Ok(ProxySource::Fixed(proxy.clone())),
+        };
+        num_of_checks += 1;
+
+        // the site is still reported, so the rest of the scan can go on
+        let proxy_source = match proxy_source {
+            Ok(proxy_source) => proxy_source,
+            Err(e) => {
+                add_error_to_channel(
+                    tx.clone(),
+                    Arc::clone(&username),
+                    Arc::from(&site[..]),
+                    Arc::clone(info),
+                    e,
+                );
+                continue;
+            }
+        };
         add_result_to_channel(
             tx.clone(),
             Arc::clone(&username),
             Arc::from(&site[..]),
             Arc::clone(info),
             *timeout,
-            proxy.clone(),
+            proxy_source,
+            user_agents.pick(site),
+            cookies.as_ref().map(|cookies| cookies.jar(site)),
+            credentials.for_site(site),
+            transport.for_site(site),
+            cassette.clone(),
+            *retries,
         )?;
     }
 
     drop(tx);
 
+    let mut progress = progress.then(|| Progress::new(num_of_checks));
+
     // save to output data struct
     let mut results = Vec::with_capacity(site_data.len());
     while let Some(result) = rx.recv().await {
@@ -65,14 +166,33 @@ pub async fn check_username(
             info,
             url,
             url_probe,
+            request,
+            proxy,
+            secrets,
+            retries,
             ..
         } = result;
 
+        // only failed requests count against a proxy, not sites that never sent one
+        let error_outcome = result.response.as_ref().err().map(ProxyOutcome::of_error);
         let query_result: QueryResult = match result.response {
             Err(e) => {
+                debug!(
+                    site = %site,
+                    url_probe,
+                    elapsed_ms = result.query_time.as_millis() as u64,
+                    error = %e,
+                    "site check failed"
+                );
                 let status = match e {
                     QueryError::InvalidUsernameError => QueryStatus::Illegal,
-                    QueryError::RequestError | QueryError::RegexError(_) => QueryStatus::Unknown,
+                    QueryError::RequestError
+                    | QueryError::ConnectionError
+                    | QueryError::RegexError(_)
+                    | QueryError::MissingCredential(_)
+                    | QueryError::InvalidBody(_)
+                    | QueryError::StepFailed { .. }
+                    | QueryError::ProxyUnavailable(_) => QueryStatus::Unknown,
                 };
                 QueryResult {
                     username: Arc::clone(&username),
@@ -83,65 +203,114 @@ pub async fn check_username(
                     http_status: None,
                     query_time: result.query_time,
                     context: Some(e.to_string()),
+                    metadata: Metadata::new(),
+                    confidence: 0.0,
                 }
             }
             Ok(response) => {
                 let status_code = response.status().as_u16();
-                let resp_text = response.text().await?;
-                let wfthit = waf_hit(&resp_text);
-
-                let error_type = &info.error_type;
-                let status = match (wfthit, error_type) {
-                    (true, _) => QueryStatus::Waf,
-                    (false, ErrorType::Message { msg }) => {
-                        let error_flag = msg.is_in(&resp_text);
-                        if error_flag {
-                            QueryStatus::Available
-                        } else {
-                            QueryStatus::Claimed
-                        }
-                    }
-                    (false, ErrorType::StatusCode { codes }) => {
-                        let mut status = QueryStatus::Claimed;
-
-                        if let Some(error_codes) = codes {
-                            if error_codes.contains(&status_code) {
-                                status = QueryStatus::Available;
-                            }
-                        } else if !(200..=399).contains(&status_code) {
-                            status = QueryStatus::Available;
-                        }
-
-                        status
-                    }
-                    (false, ErrorType::ResponseUrl { .. }) => {
-                        if (200..300).contains(&status_code) {
-                            QueryStatus::Claimed
-                        } else {
-                            QueryStatus::Available
-                        }
+                let final_url = response.url().clone();
+                let headers = response.headers().clone();
+                let dump = dump
+                    .as_ref()
+                    .filter(|dump| dump.matches(&site, status_code));
+                // a status code can't tell a WAF challenge apart from a missing profile
+                let waf_only = !(200..300).contains(&status_code);
+                let body = if info.needs_body() || dump.is_some() || waf_only {
+                    // dumps keep as much of the body as the size limit allows
+                    let limit = match info.needs_body() || dump.is_some() {
+                        true => *max_body_size,
+                        false => (*max_body_size).min(WAF_BODY_LIMIT),
+                    };
+                    let stop = |text: &str| {
+                        dump.is_none()
+                            && (matches!(&info.error_type, ErrorType::Message { msg } if msg.is_in(text))
+                                || waf.detect(status_code, &headers, text).is_some())
+                    };
+                    read_body(response, limit, stop).await
+                } else {
+                    Ok(ResponseBody::default())
+                };
+                let (body, body_error) = match body {
+                    Ok(body) => (body, None),
+                    Err(e) => (ResponseBody::default(), Some(e)),
+                };
+                let resp_text = body.text;
+                let vendor = waf.detect(status_code, &headers, &resp_text);
+
+                let (status, rule) = match &body_error {
+                    Some(_) => (QueryStatus::Unknown, "failed to read response body"),
+                    None => detect(&info, &username, status_code, &resp_text, vendor),
+                };
+                // the evidence against a profile may be past the size limit
+                let cut_off = body.truncated && status == QueryStatus::Claimed && info.needs_body();
+                let (status, rule) = match cut_off {
+                    true => (QueryStatus::Unknown, "body truncated"),
+                    false => (status, rule),
+                };
+
+                let reliability = site_reliability
+                    .as_ref()
+                    .and_then(|reliability| reliability.get(&*site).copied());
+                let confidence = confidence(
+                    &info,
+                    status,
+                    &resp_text,
+                    reliability,
+                    retries,
+                    waf.suspect(&resp_text),
+                );
+
+                debug!(
+                    site = %site,
+                    url_probe,
+                    final_url = redact(final_url.as_str(), &secrets),
+                    http_status = status_code,
+                    elapsed_ms = result.query_time.as_millis() as u64,
+                    body_bytes = resp_text.len(),
+                    body_truncated = body.truncated,
+                    body_stopped = body.stopped,
+                    rule,
+                    body_error = body_error.as_ref().map(tracing::field::display),
+                    waf = vendor,
+                    retries,
+                    verdict = ?status,
+                    confidence,
+                    "checked site"
+                );
+
+                let metadata = match &info.extract {
+                    Some(extractors) if status == QueryStatus::Claimed => {
+                        extract_metadata(extractors, &resp_text, &final_url)
                     }
+                    _ => Metadata::new(),
                 };
+                if !metadata.is_empty() {
+                    debug!(site = %site, metadata = ?metadata, "extracted profile metadata");
+                }
 
-                if *dump_response {
-                    println!("+++++++++++++++++++++");
-                    println!("TARGET NAME   : {site}");
-                    println!("USERNAME      : {username}");
-                    println!("TARGET URL    : {url_probe:?}");
-                    // TODO: Split this out into parts? Impl debug differently?
-                    println!("TEST METHOD   : {error_type:?}");
-                    println!("Results...");
-                    println!("RESPONSE CODE : {status_code}");
-                    println!(">>>>> BEGIN RESPONSE TEXT");
-                    println!("{resp_text}");
-                    println!("<<<<< END RESPONSE TEXT");
-
-                    println!("VERDICT       : {status:?}");
-                    println!("+++++++++++++++++++++");
+                if let Some(dump) = dump {
+                    let response_dump = ResponseDump {
+                        site: &site,
+                        username: &username,
+                        error_type: &info.error_type,
+                        request: request.as_ref(),
+                        url_probe: &url_probe,
+                        status_code,
+                        headers: &headers,
+                        body: &resp_text,
+                        verdict: &status,
+                    };
+                    match dump.write(&response_dump) {
+                        Ok(path) => debug!(site = %site, path = %path.display(), "dumped response"),
+                        Err(e) => warn!(site = %site, error = %e, "failed to dump response"),
+                    }
                 }
 
                 if *browse && status == QueryStatus::Claimed {
-                    open::that(&url).inspect_err(|e| eprintln!("Failed to open browser: {e}"))?;
+                    if let Err(e) = open::that(&url) {
+                        warn!(site = %site, url, error = %e, "failed to open browser");
+                    }
                 }
 
                 QueryResult {
@@ -152,16 +321,132 @@ pub async fn check_username(
                     status,
                     http_status: Some(status_code),
                     query_time: result.query_time,
-                    context: None,
+                    context: match status {
+                        QueryStatus::Unknown => Some(match &body_error {
+                            Some(e) => format!("{rule}: {e}"),
+                            None if cut_off => format!("{rule} at {max_body_size} bytes"),
+                            None => rule.to_string(),
+                        }),
+                        QueryStatus::Waf => vendor.map(str::to_string),
+                        _ => None,
+                    },
+                    metadata,
+                    confidence,
                 }
             }
         };
 
-        if *print_all || (*print_found && query_result.status == QueryStatus::Claimed) {
+        let outcome = error_outcome.unwrap_or_else(|| Some(ProxyOutcome::of(&query_result)));
+        if let (Some(pool), Some(proxy), Some(outcome)) = (proxy_pool, &proxy, outcome) {
+            pool.record(proxy, outcome);
+        }
+        if let Some(tor) = tor {
+            if outcome == Some(ProxyOutcome::Blocked) {
+                tor.rotate(&query_result.site_name).await;
+            }
+        }
+
+        if let Some(progress) = &mut progress {
+            progress.clear();
+        }
+
+        let is_visible = !query_result.is_low_confidence(*min_confidence);
+        if is_visible
+            && (*print_all || (*print_found && query_result.status == QueryStatus::Claimed))
+        {
             print_result(&query_result);
         }
+
+        if let Some(progress) = &mut progress {
+            progress.record(&query_result);
+            progress.draw();
+        }
+
         results.push(query_result);
     }
 
+    if let Some(progress) = &mut progress {
+        progress.finish();
+    }
+
     Ok(results)
 }
+
+/// Decide whether a username is claimed from a site's response.
+///
+/// Returns the verdict and a short description of the rule that decided it.
+fn detect(
+    info: &TargetInfo,
+    username: &str,
+    status_code: u16,
+    body: &str,
+    waf: Option<&str>,
+) -> (QueryStatus, &'static str) {
+    if waf.is_some() {
+        return (QueryStatus::Waf, "waf fingerprint matched");
+    }
+
+    let (status, rule) = match &info.error_type {
+        ErrorType::Message { msg } => {
+            let error_flag = msg.is_in(body);
+            if error_flag {
+                (QueryStatus::Available, "errorMsg found in body")
+            } else {
+                (QueryStatus::Claimed, "errorMsg not found in body")
+            }
+        }
+        ErrorType::StatusCode { codes } => match codes {
+            Some(error_codes) if error_codes.contains(&status_code) => {
+                (QueryStatus::Available, "status code in errorCode")
+            }
+            Some(_) => (QueryStatus::Claimed, "status code not in errorCode"),
+            None if !(200..=399).contains(&status_code) => {
+                (QueryStatus::Available, "status code outside 200-399")
+            }
+            None => (QueryStatus::Claimed, "status code within 200-399"),
+        },
+        ErrorType::ResponseUrl { .. } => {
+            if (200..300).contains(&status_code) {
+                (QueryStatus::Claimed, "no redirect to errorUrl (2xx)")
+            } else {
+                (QueryStatus::Available, "redirected to errorUrl (non-2xx)")
+            }
+        }
+        ErrorType::JsonPath { condition } => match serde_json::from_str(body) {
+            Ok(document) if condition.evaluate(&document) => {
+                (QueryStatus::Available, "errorPath matched")
+            }
+            Ok(_) => (QueryStatus::Claimed, "errorPath not matched"),
+            Err(_) => (QueryStatus::Unknown, "response body is not JSON"),
+        },
+        ErrorType::Rules { absent } => {
+            if absent.matches(status_code, body, username) {
+                (QueryStatus::Available, "absentIf matched")
+            } else {
+                (QueryStatus::Claimed, "absentIf not matched")
+            }
+        }
+    };
+
+    if status != QueryStatus::Claimed {
+        return (status, rule);
+    }
+
+    // a site can require positive evidence before a username counts as claimed
+    if let Some(claimed_msg) = &info.claimed_msg {
+        if !claimed_msg.is_in(body) {
+            return (QueryStatus::Unknown, "claimedMsg not found in body");
+        }
+    }
+
+    match &info.claimed_if {
+        Some(claimed) if claimed.matches(status_code, body, username) => {
+            (QueryStatus::Claimed, "claimedIf matched")
+        }
+        Some(_) => (
+            QueryStatus::Unknown,
+            "neither absentIf nor claimedIf matched",
+        ),
+        None => (status, rule),
+    }
+}
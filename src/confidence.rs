@@ -0,0 +1,128 @@
+use crate::query::QueryStatus;
+use crate::sherlock_target_manifest::{ErrorType, TargetInfo};
+use color_eyre::eyre::WrapErr;
+use std::collections::HashMap;
+
+/// Bodies smaller than this are unusual for a profile page.
+const SMALL_BODY_BYTES: usize = 256;
+
+/// Lost for each retry a request needed, up to three retries.
+const RETRY_PENALTY: f32 = 0.1;
+
+/// Fraction of past self-tests each site passed, keyed by site name.
+pub type SiteReliability = HashMap<String, f32>;
+
+/// Load site reliability from a JSON file such as `{"GitHub": 1.0, "Tumblr": 0.6}`.
+pub fn load_site_reliability(path: &str) -> color_eyre::Result<SiteReliability> {
+    let json = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Problem while attempting to access site history '{path}'"))?;
+    let reliability = serde_json::from_str(&json)
+        .wrap_err_with(|| format!("Problem parsing site history '{path}'"))?;
+
+    Ok(reliability)
+}
+
+/// How much a verdict can be trusted, from 0 (not at all) to 1.
+///
+/// The score starts from how reliable the site's detection method is and
+/// is adjusted for required presence evidence, bot challenge hints,
+/// suspiciously small bodies, retried requests and how often the site
+/// passed self-tests.
+#[must_use]
+pub fn confidence(
+    info: &TargetInfo,
+    status: QueryStatus,
+    body: &str,
+    reliability: Option<f32>,
+    retries: u32,
+    waf_suspect: bool,
+) -> f32 {
+    if !matches!(status, QueryStatus::Claimed | QueryStatus::Available) {
+        return 0.0;
+    }
+
+    let mut score = match info.error_type {
+        ErrorType::JsonPath { .. } => 0.85,
+        ErrorType::Rules { .. } => 0.8,
+        ErrorType::Message { .. } => 0.7,
+        ErrorType::StatusCode { .. } => 0.6,
+        ErrorType::ResponseUrl { .. } => 0.5,
+    };
+
+    // a claimed verdict had to match the site's presence evidence
+    let has_presence_evidence = info.claimed_msg.is_some() || info.claimed_if.is_some();
+    if status == QueryStatus::Claimed && has_presence_evidence {
+        score += 0.15;
+    }
+
+    if waf_suspect {
+        score -= 0.3;
+    }
+
+    // a site that needed retries is flaky, so its answer is less certain
+    score -= RETRY_PENALTY * retries.min(3) as f32;
+
+    if info.needs_body() {
+        if body.trim().is_empty() {
+            score -= 0.3;
+        } else if status == QueryStatus::Claimed && body.len() < SMALL_BODY_BYTES {
+            score -= 0.1;
+        }
+    }
+
+    (score * reliability.unwrap_or(1.0)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn target(fields: serde_json::Value) -> TargetInfo {
+        let mut entry = json!({
+            "url": "https://example.com/{}",
+            "urlMain": "https://example.com/",
+            "username_claimed": "alice",
+        });
+        for (key, value) in fields.as_object().unwrap() {
+            entry[key] = value.clone();
+        }
+        serde_json::from_value(entry).unwrap()
+    }
+
+    #[test]
+    fn test_confidence_by_detection_method() {
+        let body = "x".repeat(SMALL_BODY_BYTES);
+        let status_code = target(json!({ "errorType": "status_code" }));
+        let message = target(json!({ "errorType": "message", "errorMsg": "Not found" }));
+        let presence = target(json!({
+            "errorType": "message",
+            "errorMsg": "Not found",
+            "claimedMsg": "followers",
+        }));
+
+        let status_code = confidence(&status_code, QueryStatus::Claimed, "", None, 0, false);
+        let message = confidence(&message, QueryStatus::Claimed, &body, None, 0, false);
+        let presence = confidence(&presence, QueryStatus::Claimed, &body, None, 0, false);
+
+        assert!(status_code < message);
+        assert!(message < presence);
+    }
+
+    #[test]
+    fn test_confidence_penalties() {
+        let info = target(json!({ "errorType": "message", "errorMsg": "Not found" }));
+        let body = "x".repeat(SMALL_BODY_BYTES);
+        let baseline = confidence(&info, QueryStatus::Claimed, &body, None, 0, false);
+
+        assert!(confidence(&info, QueryStatus::Claimed, &body, None, 0, true) < baseline);
+        assert!(confidence(&info, QueryStatus::Claimed, "", None, 0, false) < baseline);
+        assert!(confidence(&info, QueryStatus::Claimed, "tiny", None, 0, false) < baseline);
+        assert!(confidence(&info, QueryStatus::Claimed, &body, Some(0.5), 0, false) < baseline);
+        assert!(confidence(&info, QueryStatus::Claimed, &body, None, 1, false) < baseline);
+        assert_eq!(
+            confidence(&info, QueryStatus::Unknown, &body, None, 0, false),
+            0.0
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use color_eyre::eyre::{self, WrapErr};
+use reqwest::cookie::Jar;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A cookie as exported by browser extensions, e.g. `{"domain": ".example.com", "name": "session", "value": "..."}`.
+#[derive(Debug, Clone, Deserialize)]
+struct JsonCookie {
+    domain: String,
+    name: String,
+    value: String,
+    #[serde(default = "default_path")]
+    path: String,
+    #[serde(default)]
+    secure: bool,
+    /// Only send the cookie to `domain` itself, not its subdomains.
+    #[serde(default, rename = "hostOnly")]
+    host_only: bool,
+    /// Expiry as seconds since the Unix epoch. Session cookies have none.
+    #[serde(default, rename = "expirationDate")]
+    expiration_date: Option<f64>,
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+impl JsonCookie {
+    fn is_expired(&self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        self.expiration_date
+            .is_some_and(|expires| expires > 0.0 && expires < now)
+    }
+
+    /// The cookie as a `Set-Cookie` value and the URL it is set from.
+    fn to_set_cookie(&self) -> color_eyre::Result<(String, Url)> {
+        let host = self.domain.trim_start_matches('.');
+        let scheme = match self.secure {
+            true => "https",
+            false => "http",
+        };
+        let url = Url::parse(&format!("{scheme}://{host}{}", self.path))
+            .wrap_err_with(|| format!("Invalid cookie domain '{}'", self.domain))?;
+
+        let mut cookie = format!("{}={}; Path={}", self.name, self.value, self.path);
+        if !self.host_only {
+            cookie.push_str(&format!("; Domain={host}"));
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+
+        Ok((cookie, url))
+    }
+}
+
+/// Parse a Netscape `cookies.txt` file, as written by curl and browser extensions.
+fn parse_netscape(contents: &str) -> color_eyre::Result<Vec<JsonCookie>> {
+    contents
+        .lines()
+        .enumerate()
+        .filter_map(|(number, line)| {
+            // curl marks HttpOnly cookies with a prefix that looks like a comment
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line).trim_end();
+            (!line.trim().is_empty() && !line.starts_with('#')).then_some((number, line))
+        })
+        .map(|(number, line)| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let [domain, include_subdomains, path, secure, expires, name, value] = fields[..]
+            else {
+                return Err(eyre::eyre!(
+                    "Expected 7 tab separated fields on line {}",
+                    number + 1
+                ));
+            };
+
+            Ok(JsonCookie {
+                domain: domain.to_string(),
+                name: name.to_string(),
+                value: value.to_string(),
+                path: path.to_string(),
+                secure: secure.eq_ignore_ascii_case("TRUE"),
+                host_only: !include_subdomains.eq_ignore_ascii_case("TRUE"),
+                expiration_date: expires.parse().ok(),
+            })
+        })
+        .collect()
+}
+
+/// Cookie jars for the sites of a scan.
+///
+/// Each site gets its own jar, so cookies set on one hop are sent on the
+/// next and survive for later usernames, but never leak to other sites.
+/// Every jar starts out with the cookies loaded from files.
+#[derive(Debug, Default)]
+pub struct CookieJars {
+    /// Loaded cookies as `Set-Cookie` values and the URL they are set from.
+    seed: Vec<(String, Url)>,
+    jars: Mutex<HashMap<String, Arc<Jar>>>,
+}
+
+impl CookieJars {
+    /// Load cookies from a Netscape `cookies.txt` file or a JSON list of cookies.
+    pub fn load(&mut self, path: &str) -> color_eyre::Result<()> {
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Problem while attempting to access cookie file '{path}'"))?;
+        let cookies = match contents.trim_start().starts_with('[') {
+            true => serde_json::from_str(&contents).map_err(eyre::Report::from),
+            false => parse_netscape(&contents),
+        }
+        .wrap_err_with(|| format!("Problem parsing cookie file '{path}'"))?;
+
+        for cookie in cookies.iter().filter(|cookie| !cookie.is_expired()) {
+            self.seed.push(cookie.to_set_cookie()?);
+        }
+
+        Ok(())
+    }
+
+    /// The jar for `site`.
+    pub fn jar(&self, site: &str) -> Arc<Jar> {
+        let mut jars = self.jars.lock().unwrap();
+        let jar = jars.entry(site.to_string()).or_insert_with(|| {
+            let jar = Jar::default();
+            for (cookie, url) in &self.seed {
+                jar.add_cookie_str(cookie, url);
+            }
+            Arc::new(jar)
+        });
+
+        Arc::clone(jar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::cookie::CookieStore;
+
+    fn cookies_for(jar: &Jar, url: &str) -> Option<String> {
+        jar.cookies(&Url::parse(url).unwrap())
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_netscape() {
+        let cookies = parse_netscape(
+            "# Netscape HTTP Cookie File\n\
+             .example.com\tTRUE\t/\tTRUE\t0\tsession\tabc\n\
+             #HttpOnly_forum.example.org\tFALSE\t/\tFALSE\t1\told\tgone\n",
+        )
+        .unwrap();
+        assert_eq!(cookies.len(), 2);
+        assert!(!cookies[0].host_only);
+        assert!(cookies[1].is_expired());
+
+        assert!(parse_netscape("example.com\tTRUE\t/\n").is_err());
+    }
+
+    #[test]
+    fn test_jars() {
+        let mut jars = CookieJars::default();
+        for cookie in parse_netscape(".example.com\tTRUE\t/\tTRUE\t0\tsession\tabc\n").unwrap() {
+            jars.seed.push(cookie.to_set_cookie().unwrap());
+        }
+
+        let jar = jars.jar("Example");
+        assert_eq!(
+            cookies_for(&jar, "https://www.example.com/alice"),
+            Some("session=abc".to_string())
+        );
+        assert_eq!(cookies_for(&jar, "http://www.example.com/alice"), None);
+        assert_eq!(cookies_for(&jar, "https://other.com/"), None);
+
+        // cookies set for one site stay in its jar
+        jar.add_cookie_str("consent=yes", &Url::parse("https://other.com/").unwrap());
+        assert!(cookies_for(&jars.jar("Example"), "https://other.com/").is_some());
+        assert!(cookies_for(&jars.jar("Other"), "https://other.com/").is_none());
+    }
+}
@@ -0,0 +1,293 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use color_eyre::eyre::{self, WrapErr};
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::LazyLock;
+use thiserror::Error;
+
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{credential\.([A-Za-z]+)\}").unwrap());
+
+/// A placeholder referenced a credential that isn't configured.
+#[derive(Error, Debug)]
+#[error("No {0} credential configured for this site")]
+pub struct MissingCredential(pub String);
+
+/// Whether `text` holds a `{credential.<kind>}` placeholder.
+pub fn has_placeholder(text: &str) -> bool {
+    PLACEHOLDER.is_match(text)
+}
+
+/// The kinds of credential a manifest can reference as `{credential.<kind>}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CredentialKind {
+    Bearer,
+    ApiKey,
+    /// `username:password`, base64 encoded for an `Authorization: Basic` header.
+    Basic,
+    Cookie,
+}
+
+impl CredentialKind {
+    const ALL: [CredentialKind; 4] = [Self::Bearer, Self::ApiKey, Self::Basic, Self::Cookie];
+
+    /// The name used in placeholders, e.g. `apiKey`.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bearer => "bearer",
+            Self::ApiKey => "apiKey",
+            Self::Basic => "basic",
+            Self::Cookie => "cookie",
+        }
+    }
+
+    /// The environment variable holding this credential for `site`,
+    /// e.g. `SHERLOCK_CREDENTIAL_STACK_OVERFLOW_API_KEY`.
+    #[must_use]
+    pub fn env_var(self, site: &str) -> String {
+        let site = site
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect::<String>();
+        let kind = match self {
+            Self::Bearer => "BEARER",
+            Self::ApiKey => "API_KEY",
+            Self::Basic => "BASIC",
+            Self::Cookie => "COOKIE",
+        };
+
+        format!("SHERLOCK_CREDENTIAL_{site}_{kind}")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct BasicAuth {
+    username: String,
+    password: String,
+}
+
+/// The credentials for one site in a secrets file.
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct CredentialEntry {
+    bearer: Option<String>,
+    api_key: Option<String>,
+    basic: Option<BasicAuth>,
+    cookie: Option<String>,
+}
+
+impl CredentialEntry {
+    /// Drop the whitespace around every value, such as the trailing newline
+    /// of a pasted token, which is not valid in a header.
+    fn trim(&mut self) {
+        let values = [&mut self.bearer, &mut self.api_key, &mut self.cookie];
+        for value in values.into_iter().flatten() {
+            *value = value.trim().to_string();
+        }
+        if let Some(basic) = &mut self.basic {
+            basic.username = basic.username.trim().to_string();
+            basic.password = basic.password.trim().to_string();
+        }
+    }
+}
+
+/// Credentials keyed by site name, from a secrets file and the environment.
+#[derive(Clone, Default)]
+pub struct Credentials {
+    sites: HashMap<String, CredentialEntry>,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("sites", &self.sites.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Credentials {
+    /// Load a JSON secrets file such as `{"GitHub": {"bearer": "..."}}`.
+    pub fn load(path: &str) -> color_eyre::Result<Self> {
+        let json = std::fs::read_to_string(path).wrap_err_with(|| {
+            format!("Problem while attempting to access secrets file '{path}'")
+        })?;
+        let deserializer = &mut serde_json::Deserializer::from_str(&json);
+        let mut sites: HashMap<String, CredentialEntry> =
+            serde_path_to_error::deserialize(deserializer)
+                .map_err(|err| eyre::eyre!("{} at [{}]", err.inner(), err.path()))
+                .wrap_err_with(|| format!("Problem parsing secrets file '{path}'"))?;
+        sites.values_mut().for_each(CredentialEntry::trim);
+
+        Ok(Self { sites })
+    }
+
+    /// The credentials available for `site`. Environment variables take
+    /// precedence over the secrets file.
+    #[must_use]
+    pub fn for_site(&self, site: &str) -> SiteCredentials {
+        let entry = self.sites.get(site).cloned().unwrap_or_default();
+        let values = CredentialKind::ALL
+            .into_iter()
+            .filter_map(|kind| {
+                let from_env = std::env::var(kind.env_var(site))
+                    .ok()
+                    .map(|value| value.trim().to_string());
+                let value = match kind {
+                    CredentialKind::Bearer => from_env.or(entry.bearer.clone()),
+                    CredentialKind::ApiKey => from_env.or(entry.api_key.clone()),
+                    CredentialKind::Basic => from_env
+                        .or_else(|| {
+                            entry
+                                .basic
+                                .as_ref()
+                                .map(|basic| format!("{}:{}", basic.username, basic.password))
+                        })
+                        .map(|basic| BASE64.encode(basic)),
+                    CredentialKind::Cookie => from_env.or(entry.cookie.clone()),
+                };
+                value.map(|value| (kind, value))
+            })
+            .collect();
+
+        SiteCredentials { values }
+    }
+}
+
+/// The resolved credentials of one site.
+#[derive(Clone, Default)]
+pub struct SiteCredentials {
+    values: HashMap<CredentialKind, String>,
+}
+
+impl fmt::Debug for SiteCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SiteCredentials")
+            .field("kinds", &self.values.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl SiteCredentials {
+    /// Replace the `{credential.<kind>}` placeholders in `text`.
+    ///
+    /// Every credential used is added to `secrets` so it can be redacted
+    /// from anything written out later.
+    pub fn resolve(
+        &self,
+        text: &str,
+        secrets: &mut Vec<String>,
+    ) -> Result<String, MissingCredential> {
+        let mut missing = None;
+        let resolved = PLACEHOLDER.replace_all(text, |captures: &Captures| {
+            let name = &captures[1];
+            let value = CredentialKind::ALL
+                .into_iter()
+                .find(|kind| kind.name() == name)
+                .and_then(|kind| self.values.get(&kind));
+            match value {
+                Some(value) => {
+                    secrets.push(value.clone());
+                    value.clone()
+                }
+                None => {
+                    missing.get_or_insert_with(|| name.to_string());
+                    String::new()
+                }
+            }
+        });
+
+        match missing {
+            Some(name) => Err(MissingCredential(name)),
+            None => Ok(resolved.into_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_var() {
+        assert_eq!(
+            CredentialKind::ApiKey.env_var("Stack Overflow"),
+            "SHERLOCK_CREDENTIAL_STACK_OVERFLOW_API_KEY"
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let credentials = Credentials {
+            sites: serde_json::from_value(serde_json::json!({
+                "Forum": {
+                    "bearer": "token",
+                    "basic": { "username": "user", "password": "pass" },
+                },
+            }))
+            .unwrap(),
+        };
+
+        let forum = credentials.for_site("Forum");
+        let mut secrets = Vec::new();
+        assert_eq!(
+            forum
+                .resolve("Bearer {credential.bearer}", &mut secrets)
+                .unwrap(),
+            "Bearer token"
+        );
+        assert_eq!(
+            forum.resolve("{credential.basic}", &mut secrets).unwrap(),
+            "dXNlcjpwYXNz"
+        );
+        assert_eq!(
+            forum
+                .resolve("https://{}.example.com", &mut secrets)
+                .unwrap(),
+            "https://{}.example.com"
+        );
+        assert_eq!(secrets, ["token", "dXNlcjpwYXNz"]);
+
+        assert!(matches!(
+            forum.resolve("{credential.apiKey}", &mut secrets),
+            Err(MissingCredential(name)) if name == "apiKey"
+        ));
+        assert!(credentials
+            .for_site("Other")
+            .resolve("{credential.bearer}", &mut secrets)
+            .is_err());
+    }
+
+    #[test]
+    fn test_load_trims_values() {
+        let path = std::env::temp_dir().join("sherlock-credentials-test.json");
+        std::fs::write(
+            &path,
+            r#"{"Forum": {"bearer": "token\n", "basic": {"username": " user", "password": "pass\r\n"}}}"#,
+        )
+        .unwrap();
+
+        let credentials = Credentials::load(path.to_str().unwrap()).unwrap();
+        let forum = credentials.for_site("Forum");
+        let mut secrets = Vec::new();
+        assert_eq!(
+            forum.resolve("{credential.bearer}", &mut secrets).unwrap(),
+            "token"
+        );
+        assert_eq!(
+            forum.resolve("{credential.basic}", &mut secrets).unwrap(),
+            "dXNlcjpwYXNz"
+        );
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        let entry = serde_json::from_value::<CredentialEntry>(serde_json::json!({ "token": "x" }));
+        assert!(entry.is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use crate::query::{QueryResult, QueryStatus};
+use crate::sherlock_target_manifest::TargetInfo;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, LazyLock};
+
+static EMBEDDED_URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)https?://[^\s<>"']+"#).unwrap());
+
+/// An `@handle` that isn't part of an email address or another handle.
+static MENTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:^|[^A-Za-z0-9_.@])@([A-Za-z0-9_](?:[A-Za-z0-9_.\-]*[A-Za-z0-9_])?)").unwrap()
+});
+
+/// A username found on a claimed profile.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct Link {
+    /// The username whose profile contained the link.
+    pub username: String,
+    /// The site the profile is on.
+    pub site: String,
+    /// The linked username.
+    pub linked_username: String,
+    /// The site the link points at, if it was a profile URL rather than a mention.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub linked_site: Option<String>,
+}
+
+/// Recognises profile URLs of the sites in the manifest.
+#[derive(Debug)]
+pub struct ProfileMatcher {
+    patterns: Vec<(String, Regex)>,
+}
+
+impl ProfileMatcher {
+    /// Build a matcher from the `url` templates of every target.
+    #[must_use]
+    pub fn new(targets: &HashMap<String, Arc<TargetInfo>>) -> Self {
+        let mut patterns = targets
+            .iter()
+            .filter_map(|(site, info)| Some((site.clone(), url_template_regex(&info.url)?)))
+            .collect::<Vec<_>>();
+        // keep matching deterministic when several templates match the same URL
+        patterns.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Self { patterns }
+    }
+
+    /// The site and username a profile URL points at.
+    #[must_use]
+    pub fn match_url(&self, url: &str) -> Option<(&str, String)> {
+        self.patterns.iter().find_map(|(site, regex)| {
+            let username = regex.captures(url)?.get(1)?.as_str();
+            Some((site.as_str(), username.to_string()))
+        })
+    }
+}
+
+/// Turn a manifest URL template such as `https://www.github.com/{}` into a
+/// regex matching profile URLs with any scheme, with or without `www.`.
+fn url_template_regex(template: &str) -> Option<Regex> {
+    let (before, after) = template.split_once("{}")?;
+    let strip = |s: &str| -> String {
+        let s = s
+            .strip_prefix("https://")
+            .or_else(|| s.strip_prefix("http://"))
+            .unwrap_or(s);
+        s.strip_prefix("www.").unwrap_or(s).to_string()
+    };
+    let before = strip(before);
+    let after = after.trim_end_matches('/');
+
+    Regex::new(&format!(
+        r"(?i)^(?:https?://)?(?:www\.)?{}([A-Za-z0-9_.\-]+){}/?(?:[?#].*)?$",
+        regex::escape(&before),
+        regex::escape(after),
+    ))
+    .ok()
+}
+
+/// A queue of usernames to check that grows as linked usernames are found.
+///
+/// Usernames are checked at most once. Linked usernames are only queued while
+/// their depth is within `max_depth` and fewer than `max_usernames` usernames
+/// have been queued in total.
+#[derive(Debug)]
+pub struct Discovery {
+    matcher: ProfileMatcher,
+    queue: VecDeque<(String, usize)>,
+    seen: HashSet<String>,
+    links: Vec<Link>,
+    max_depth: usize,
+    max_usernames: usize,
+}
+
+impl Discovery {
+    #[must_use]
+    pub fn new(
+        usernames: Vec<String>,
+        targets: &HashMap<String, Arc<TargetInfo>>,
+        max_depth: usize,
+        max_usernames: usize,
+    ) -> Self {
+        let mut discovery = Self {
+            matcher: ProfileMatcher::new(targets),
+            queue: VecDeque::new(),
+            seen: HashSet::new(),
+            links: Vec::new(),
+            max_depth,
+            max_usernames,
+        };
+
+        for username in usernames {
+            discovery.enqueue(username, 0);
+        }
+
+        discovery
+    }
+
+    /// The next username to check and how many links away from the initial usernames it is.
+    pub fn next_username(&mut self) -> Option<(String, usize)> {
+        self.queue.pop_front()
+    }
+
+    /// Record the links on the claimed profiles of a checked username and
+    /// queue the linked usernames.
+    pub fn record(&mut self, depth: usize, results: &[QueryResult]) {
+        for result in results {
+            if result.status != QueryStatus::Claimed {
+                continue;
+            }
+
+            let found = result
+                .metadata
+                .values()
+                .flatten()
+                .flat_map(|value| self.find_usernames(value))
+                .collect::<Vec<_>>();
+            for (linked_site, linked_username) in found {
+                let is_self_link = linked_username.eq_ignore_ascii_case(&result.username)
+                    && linked_site.as_deref() == Some(&*result.site_name);
+                if is_self_link {
+                    continue;
+                }
+
+                let link = Link {
+                    username: result.username.to_string(),
+                    site: result.site_name.to_string(),
+                    linked_username: linked_username.clone(),
+                    linked_site,
+                };
+                if !self.links.contains(&link) {
+                    self.links.push(link);
+                }
+
+                if depth < self.max_depth {
+                    self.enqueue(linked_username, depth + 1);
+                }
+            }
+        }
+    }
+
+    /// Every link found so far, forming a graph of username → site → linked username.
+    #[must_use]
+    pub fn links(&self) -> &[Link] {
+        &self.links
+    }
+
+    fn enqueue(&mut self, username: String, depth: usize) {
+        if self.seen.len() >= self.max_usernames && depth > 0 {
+            return;
+        }
+
+        if self.seen.insert(username.to_lowercase()) {
+            self.queue.push_back((username, depth));
+        }
+    }
+
+    /// The usernames in a metadata value, in the order they appear: profile
+    /// URLs of known sites and `@handle` mentions, also inside longer text
+    /// such as a bio.
+    fn find_usernames(&self, value: &str) -> Vec<(Option<String>, String)> {
+        if let Some((site, username)) = self.matcher.match_url(value.trim()) {
+            return vec![(Some(site.to_string()), username)];
+        }
+
+        let mut found = Vec::new();
+        for url in EMBEDDED_URL.find_iter(value) {
+            let trimmed = url
+                .as_str()
+                .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']']);
+            if let Some((site, username)) = self.matcher.match_url(trimmed) {
+                found.push((url.start(), Some(site.to_string()), username));
+            }
+        }
+
+        // handles inside URLs are part of the URL, not mentions
+        let text =
+            EMBEDDED_URL.replace_all(value, |caps: &regex::Captures| " ".repeat(caps[0].len()));
+        for mention in MENTION.captures_iter(&text) {
+            let handle = mention.get(1).expect("the pattern has one group");
+            found.push((handle.start(), None, handle.as_str().to_string()));
+        }
+
+        found.sort_by_key(|(start, _, _)| *start);
+        found
+            .into_iter()
+            .map(|(_, site, username)| (site, username))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::Metadata;
+    use std::time::Duration;
+
+    fn targets() -> HashMap<String, Arc<TargetInfo>> {
+        let manifest = serde_json::json!({
+            "GitHub": {
+                "url": "https://www.github.com/{}",
+                "urlMain": "https://www.github.com/",
+                "username_claimed": "blue",
+                "errorType": "status_code",
+            },
+            "Tumblr": {
+                "url": "https://{}.tumblr.com/",
+                "urlMain": "https://tumblr.com/",
+                "username_claimed": "blue",
+                "errorType": "status_code",
+            },
+        });
+        serde_json::from_value::<HashMap<String, TargetInfo>>(manifest)
+            .unwrap()
+            .into_iter()
+            .map(|(site, info)| (site, Arc::new(info)))
+            .collect()
+    }
+
+    fn claimed(username: &str, site: &str, links: &[&str]) -> QueryResult {
+        let targets = targets();
+        let mut metadata = Metadata::new();
+        metadata.insert(
+            String::from("links"),
+            links.iter().map(|link| link.to_string()).collect(),
+        );
+
+        QueryResult {
+            username: Arc::from(username),
+            site_name: Arc::from(site),
+            info: Arc::clone(&targets[site]),
+            site_url_user: String::new(),
+            status: QueryStatus::Claimed,
+            http_status: Some(200),
+            query_time: Duration::ZERO,
+            context: None,
+            metadata,
+            confidence: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_match_profile_urls() {
+        let matcher = ProfileMatcher::new(&targets());
+
+        assert_eq!(
+            matcher.match_url("https://github.com/octocat"),
+            Some(("GitHub", String::from("octocat")))
+        );
+        assert_eq!(
+            matcher.match_url("http://www.GitHub.com/octocat/?tab=repos"),
+            Some(("GitHub", String::from("octocat")))
+        );
+        assert_eq!(
+            matcher.match_url("https://staff.tumblr.com/"),
+            Some(("Tumblr", String::from("staff")))
+        );
+        assert_eq!(matcher.match_url("https://github.com/octocat/repo"), None);
+        assert_eq!(matcher.match_url("https://example.com/octocat"), None);
+    }
+
+    #[test]
+    fn test_discovery_limits_and_dedup() {
+        let mut discovery = Discovery::new(vec![String::from("alice")], &targets(), 1, 3);
+        assert_eq!(discovery.next_username(), Some((String::from("alice"), 0)));
+
+        discovery.record(
+            0,
+            &[claimed(
+                "alice",
+                "GitHub",
+                &[
+                    "https://github.com/alice",
+                    "https://github.com/alice-dev",
+                    "@alice_art",
+                    "https://ALICE-DEV.tumblr.com/",
+                    "https://github.com/too-many",
+                ],
+            )],
+        );
+
+        assert_eq!(
+            discovery.next_username(),
+            Some((String::from("alice-dev"), 1))
+        );
+        assert_eq!(
+            discovery.next_username(),
+            Some((String::from("alice_art"), 1))
+        );
+        assert_eq!(discovery.next_username(), None);
+        assert_eq!(discovery.links().len(), 4);
+        assert_eq!(discovery.links()[1].linked_site, None);
+
+        // links found at the maximum depth are recorded but not followed
+        discovery.record(
+            1,
+            &[claimed("alice-dev", "GitHub", &["https://github.com/bob"])],
+        );
+        assert_eq!(discovery.next_username(), None);
+        assert_eq!(discovery.links().len(), 5);
+    }
+
+    #[test]
+    fn test_find_usernames_in_text() {
+        let discovery = Discovery::new(Vec::new(), &targets(), 1, 10);
+        let bio = "Art by @alice_art. Code at https://github.com/alice-dev, \
+                   mail alice@example.com or see https://example.com/@nope (@bob.)";
+
+        assert_eq!(
+            discovery.find_usernames(bio),
+            vec![
+                (None, String::from("alice_art")),
+                (Some(String::from("GitHub")), String::from("alice-dev")),
+                (None, String::from("bob")),
+            ]
+        );
+        assert_eq!(
+            discovery.find_usernames("@carol"),
+            vec![(None, String::from("carol"))]
+        );
+        assert!(discovery.find_usernames("no handles here").is_empty());
+    }
+}
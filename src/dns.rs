@@ -0,0 +1,349 @@
+use color_eyre::eyre;
+use futures::future::join;
+use hickory_resolver::config::{
+    LookupIpStrategy, NameServerConfig, ResolveHosts, ResolverConfig, ResolverOpts,
+};
+use hickory_resolver::name_server::TokioConnectionProvider;
+use hickory_resolver::proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_resolver::proto::rr::{RData, RecordType};
+use hickory_resolver::proto::xfer::Protocol;
+use hickory_resolver::proto::ProtoError;
+use hickory_resolver::{ResolveError, TokioResolver};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::{Certificate, Client, Proxy, Url};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+/// How long to wait for each nameserver to answer a query.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum DnsError {
+    #[error(transparent)]
+    Resolve(#[from] ResolveError),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("Malformed DNS message: {0}")]
+    Malformed(#[from] ProtoError),
+    #[error("DNS server failed with response code {0}")]
+    ServerFailure(ResponseCode),
+}
+
+/// Where hostnames are looked up instead of the system resolver.
+#[derive(Debug, Clone)]
+pub enum Nameservers {
+    /// Plain DNS, trying each server in turn. Truncated answers are asked for
+    /// again over TCP.
+    Udp(Vec<SocketAddr>),
+    /// DNS-over-HTTPS (RFC 8484) at this URL, e.g. `https://1.1.1.1/dns-query`.
+    Https(Url),
+}
+
+/// Resolves hostnames for the HTTP client with the configured nameservers.
+#[derive(Clone)]
+pub struct Resolver {
+    backend: Backend,
+}
+
+#[derive(Clone)]
+enum Backend {
+    Udp(Box<TokioResolver>),
+    Https { url: Url, client: Client },
+}
+
+impl fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.backend {
+            Backend::Udp(_) => f.write_str("Resolver::Udp"),
+            Backend::Https { url, .. } => {
+                f.debug_struct("Resolver::Https").field("url", url).finish()
+            }
+        }
+    }
+}
+
+impl Resolver {
+    /// `root_certificates` are trusted by the DNS-over-HTTPS client in
+    /// addition to the system's, and its queries are sent through `proxy`
+    /// so they don't leave the local network directly.
+    pub fn new(
+        nameservers: Nameservers,
+        root_certificates: &[Certificate],
+        proxy: Option<&str>,
+    ) -> color_eyre::Result<Self> {
+        let backend = match nameservers {
+            Nameservers::Udp(servers) if servers.is_empty() => {
+                return Err(eyre::eyre!("No DNS servers configured"))
+            }
+            Nameservers::Udp(servers) => {
+                let config = servers
+                    .into_iter()
+                    .flat_map(|server| {
+                        [Protocol::Udp, Protocol::Tcp]
+                            .map(|protocol| NameServerConfig::new(server, protocol))
+                    })
+                    .fold(ResolverConfig::new(), |mut config, server| {
+                        config.add_name_server(server);
+                        config
+                    });
+                let mut options = ResolverOpts::default();
+                options.timeout = QUERY_TIMEOUT;
+                options.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+                options.use_hosts_file = ResolveHosts::Never;
+                // servers are tried in the order they were given
+                options.num_concurrent_reqs = 1;
+
+                let resolver =
+                    TokioResolver::builder_with_config(config, TokioConnectionProvider::default())
+                        .with_options(options)
+                        .build();
+                Backend::Udp(Box::new(resolver))
+            }
+            Nameservers::Https(url) => {
+                let builder = root_certificates
+                    .iter()
+                    .cloned()
+                    .fold(Client::builder(), |builder, certificate| {
+                        builder.add_root_certificate(certificate)
+                    })
+                    .timeout(QUERY_TIMEOUT);
+                let builder = match proxy {
+                    Some(proxy) => builder.proxy(Proxy::all(proxy)?),
+                    None => builder.no_proxy(),
+                };
+
+                Backend::Https {
+                    url,
+                    client: builder.build()?,
+                }
+            }
+        };
+
+        Ok(Self { backend })
+    }
+
+    /// The IPv4 and IPv6 addresses of `host`, IPv4 first.
+    pub async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, DnsError> {
+        let addresses = match &self.backend {
+            Backend::Udp(resolver) => resolver.lookup_ip(host).await?.iter().collect(),
+            Backend::Https { url, client } => {
+                let (v4, v6) = join(
+                    query_https(client, url, host, RecordType::A),
+                    query_https(client, url, host, RecordType::AAAA),
+                )
+                .await;
+                match (v4, v6) {
+                    (Err(e), Err(_)) => return Err(e),
+                    (v4, v6) => v4
+                        .unwrap_or_default()
+                        .into_iter()
+                        .chain(v6.unwrap_or_default())
+                        .collect::<Vec<_>>(),
+                }
+            }
+        };
+        debug!(host, ?addresses, "resolved host");
+
+        Ok(addresses)
+    }
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let addresses = resolver.lookup(name.as_str()).await?;
+            // port 0 is replaced with the port of the URL
+            let addrs: Addrs = Box::new(
+                addresses
+                    .into_iter()
+                    .map(|address| SocketAddr::new(address, 0)),
+            );
+            Ok(addrs)
+        })
+    }
+}
+
+async fn query_https(
+    client: &Client,
+    url: &Url,
+    host: &str,
+    record_type: RecordType,
+) -> Result<Vec<IpAddr>, DnsError> {
+    // the ID is always zero so answers can be cached by HTTP caches
+    let response = client
+        .post(url.clone())
+        .header(CONTENT_TYPE, "application/dns-message")
+        .header(ACCEPT, "application/dns-message")
+        .body(encode_query(host, record_type)?)
+        .send()
+        .await?
+        .error_for_status()?;
+    decode_response(&response.bytes().await?)
+}
+
+/// A recursive query for one record type of `host`.
+fn encode_query(host: &str, record_type: RecordType) -> Result<Vec<u8>, DnsError> {
+    let name = hickory_resolver::Name::from_ascii(host)?;
+    let mut message = Message::new();
+    message
+        .set_id(0)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(name, record_type));
+
+    Ok(message.to_vec()?)
+}
+
+/// The A and AAAA records in the answer section of a response.
+fn decode_response(message: &[u8]) -> Result<Vec<IpAddr>, DnsError> {
+    let message = Message::from_vec(message)?;
+    match message.response_code() {
+        ResponseCode::NoError => {}
+        ResponseCode::NXDomain => return Ok(Vec::new()),
+        code => return Err(DnsError::ServerFailure(code)),
+    }
+
+    // other records, like the CNAMEs leading to the address, are skipped
+    let addresses = message
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            RData::A(a) => Some(IpAddr::V4(a.0)),
+            RData::AAAA(aaaa) => Some(IpAddr::V6(aaaa.0)),
+            _ => None,
+        })
+        .collect();
+
+    Ok(addresses)
+}
+
+/// Parse a DNS server address, with port 53 if none is given.
+pub fn parse_nameserver(value: &str) -> Result<SocketAddr, String> {
+    value
+        .parse::<SocketAddr>()
+        .or_else(|_| value.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|_| format!("'{value}' is not an IP address with an optional port"))
+}
+
+/// Parse a DNS-over-HTTPS URL.
+pub fn parse_doh_url(value: &str) -> Result<Url, String> {
+    let url = Url::parse(value).map_err(|e| format!("'{value}' is not a URL: {e}"))?;
+    match url.scheme() {
+        "https" => Ok(url),
+        scheme => Err(format!("DNS-over-HTTPS needs an https URL, not {scheme}")),
+    }
+}
+
+/// Parse a `host:ip` override, e.g. `github.com:127.0.0.1` or `github.com:[::1]`.
+pub fn parse_override(value: &str) -> Result<(String, IpAddr), String> {
+    let (host, ip) = value
+        .split_once(':')
+        .filter(|(host, _)| !host.is_empty())
+        .ok_or_else(|| format!("'{value}' is not in the form host:ip"))?;
+    let ip = ip
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .map_err(|_| format!("'{ip}' is not an IP address"))?;
+
+    Ok((host.to_ascii_lowercase(), ip))
+}
+
+/// Warn when a proxy resolves hostnames with the local DNS, which shows
+/// the local network every site that is checked.
+pub fn warn_local_resolution(proxy: &str) {
+    if let Ok(url) = Url::parse(proxy) {
+        if url.scheme() == "socks5" {
+            warn!(
+                proxy = url.host_str(),
+                "socks5:// proxies resolve hostnames locally; use socks5h:// to resolve them through the proxy"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    /// A response to `encode_query("a.example", RecordType::A)` with a CNAME and an A record.
+    fn response() -> Vec<u8> {
+        let mut message = encode_query("a.example", RecordType::A).unwrap();
+        message[2] = 0x81;
+        message[3] = 0x80;
+        message[7] = 2;
+        // CNAME pointing the question name at b.example
+        message.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 11]);
+        message.extend_from_slice(&[1, b'b', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0]);
+        // A record for the CNAME target
+        message.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 1]);
+        message
+    }
+
+    #[test]
+    fn test_encode_query() {
+        let query = encode_query("github.com.", RecordType::AAAA).unwrap();
+        assert_eq!(&query[..4], &[0, 0, 0x01, 0x00]);
+        assert_eq!(
+            &query[12..],
+            b"\x06github\x03com\x00\x00\x1c\x00\x01".as_slice()
+        );
+        assert!(encode_query("bad..name", RecordType::A).is_err());
+    }
+
+    #[test]
+    fn test_decode_response() {
+        assert_eq!(
+            decode_response(&response()).unwrap(),
+            vec![IpAddr::from([10, 0, 0, 1])]
+        );
+        assert!(matches!(
+            decode_response(&response()[..40]),
+            Err(DnsError::Malformed(_))
+        ));
+
+        let mut nxdomain = response();
+        nxdomain[3] = 0x83;
+        assert!(decode_response(&nxdomain).unwrap().is_empty());
+        let mut refused = response();
+        refused[3] = 0x85;
+        assert!(matches!(
+            decode_response(&refused),
+            Err(DnsError::ServerFailure(ResponseCode::Refused))
+        ));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_nameserver("1.1.1.1"),
+            Ok(SocketAddr::from(([1, 1, 1, 1], 53)))
+        );
+        assert_eq!(
+            parse_nameserver("[::1]:5353"),
+            Ok(SocketAddr::from((Ipv6Addr::LOCALHOST, 5353)))
+        );
+        assert!(parse_nameserver("dns.google").is_err());
+
+        assert!(parse_doh_url("https://1.1.1.1/dns-query").is_ok());
+        assert!(parse_doh_url("http://1.1.1.1/dns-query").is_err());
+
+        assert_eq!(
+            parse_override("GitHub.com:127.0.0.1"),
+            Ok(("github.com".to_string(), IpAddr::from([127, 0, 0, 1])))
+        );
+        assert_eq!(
+            parse_override("github.com:[::1]"),
+            Ok(("github.com".to_string(), IpAddr::from(Ipv6Addr::LOCALHOST)))
+        );
+        assert!(parse_override("github.com").is_err());
+        assert!(parse_override(":127.0.0.1").is_err());
+    }
+}
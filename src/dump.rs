@@ -0,0 +1,152 @@
+use crate::query::QueryStatus;
+use crate::requests::SentRequest;
+use crate::sherlock_target_manifest::ErrorType;
+use crate::utils::write_atomic;
+use reqwest::header::HeaderMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Options for dumping responses to disk
+#[derive(Debug, Clone)]
+pub struct DumpOptions {
+    /// Directory the dumps are written to, one subdirectory per username.
+    pub dir: PathBuf,
+    /// Only dump these sites. Every site is dumped when empty.
+    pub sites: Vec<String>,
+    /// Only dump responses with these HTTP status codes. Every status is dumped when empty.
+    pub statuses: Vec<u16>,
+}
+
+/// A single HTTP exchange and the verdict reached for it.
+#[derive(Debug)]
+pub struct ResponseDump<'a> {
+    pub site: &'a str,
+    pub username: &'a str,
+    pub error_type: &'a ErrorType,
+    pub request: Option<&'a SentRequest>,
+    pub url_probe: &'a str,
+    pub status_code: u16,
+    pub headers: &'a HeaderMap,
+    pub body: &'a str,
+    pub verdict: &'a QueryStatus,
+}
+
+impl DumpOptions {
+    /// Whether a response from `site` with `status_code` should be dumped.
+    #[must_use]
+    pub fn matches(&self, site: &str, status_code: u16) -> bool {
+        (self.sites.is_empty() || self.sites.iter().any(|s| s.eq_ignore_ascii_case(site)))
+            && (self.statuses.is_empty() || self.statuses.contains(&status_code))
+    }
+
+    /// The file a dump for `site` and `username` is written to.
+    ///
+    /// Dumps for the same site land at the same relative path for every
+    /// username so that two scans can be compared with `diff -r`.
+    #[must_use]
+    pub fn path_for(&self, username: &str, site: &str) -> PathBuf {
+        self.dir
+            .join(sanitize_file_name(username))
+            .join(format!("{}.txt", sanitize_file_name(site)))
+    }
+
+    /// Write a dump of the exchange to its file, replacing any previous dump.
+    pub fn write(&self, dump: &ResponseDump) -> io::Result<PathBuf> {
+        let path = self.path_for(dump.username, dump.site);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        write_atomic(&path, |file| write_dump(file, dump))?;
+
+        Ok(path)
+    }
+}
+
+fn write_dump(file: &mut impl Write, dump: &ResponseDump) -> io::Result<()> {
+    writeln!(file, "TARGET NAME   : {}", dump.site)?;
+    writeln!(file, "USERNAME      : {}", dump.username)?;
+    writeln!(file, "TARGET URL    : {}", dump.url_probe)?;
+    writeln!(file, "TEST METHOD   : {:?}", dump.error_type)?;
+
+    writeln!(file, ">>>>> BEGIN REQUEST")?;
+    match dump.request {
+        Some(request) => {
+            writeln!(file, "{} {}", request.method, request.url)?;
+            write_headers(file, &request.headers)?;
+        }
+        None => writeln!(file, "(request not recorded)")?,
+    }
+    writeln!(file, "<<<<< END REQUEST")?;
+
+    writeln!(file, ">>>>> BEGIN RESPONSE")?;
+    writeln!(file, "RESPONSE CODE : {}", dump.status_code)?;
+    write_headers(file, dump.headers)?;
+    writeln!(file)?;
+    writeln!(file, "{}", dump.body)?;
+    writeln!(file, "<<<<< END RESPONSE")?;
+
+    writeln!(file, "VERDICT       : {:?}", dump.verdict)
+}
+
+fn write_headers(file: &mut impl Write, headers: &HeaderMap) -> io::Result<()> {
+    for (name, value) in headers {
+        writeln!(
+            file,
+            "{}: {}",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Replace characters that can't be used in a file name on common platforms.
+///
+/// Leading dots are replaced too, so `.` and `..` can't leave the dump directory.
+fn sanitize_file_name(name: &str) -> String {
+    let leading_dots = name.len() - name.trim_start_matches('.').len();
+    let sanitized = name
+        .chars()
+        .enumerate()
+        .map(|(index, c)| match c {
+            '.' if index < leading_dots => '_',
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect::<String>();
+
+    match sanitized.is_empty() {
+        true => String::from("_"),
+        false => sanitized,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_stays_in_dump_dir() {
+        let options = DumpOptions {
+            dir: PathBuf::from("dumps"),
+            sites: Vec::new(),
+            statuses: Vec::new(),
+        };
+
+        assert_eq!(
+            options.path_for("..", "GitHub"),
+            PathBuf::from("dumps/__/GitHub.txt")
+        );
+        assert_eq!(options.path_for(".", ".."), PathBuf::from("dumps/_/__.txt"));
+        assert_eq!(
+            options.path_for("../../etc", "Git.Hub"),
+            PathBuf::from("dumps/___.._etc/Git.Hub.txt")
+        );
+        assert_eq!(
+            options.path_for("", "a/b"),
+            PathBuf::from("dumps/_/a_b.txt")
+        );
+    }
+}
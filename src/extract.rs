@@ -0,0 +1,231 @@
+use crate::json_path::{JsonPath, JsonPathError};
+use regex::Regex;
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+/// Profile metadata pulled from a claimed page, e.g. `name`, `bio` or `links`.
+pub type Metadata = BTreeMap<String, Vec<String>>;
+
+#[derive(Error, Debug)]
+pub enum ExtractorError {
+    #[error("extractor must set exactly one of `css`, `regex` or `json`")]
+    AmbiguousRule,
+    #[error("invalid CSS selector '{0}'")]
+    Selector(String),
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+    #[error(transparent)]
+    JsonPath(#[from] JsonPathError),
+}
+
+/// An extraction rule as written in the manifest.
+///
+/// ```json
+/// "extract": {
+///   "name": { "css": "h1.profile-name" },
+///   "avatar": { "css": "img.avatar", "attr": "src" },
+///   "links": { "css": "a[rel=me]", "attr": "href", "all": true },
+///   "followers": { "regex": "([0-9,]+) followers" },
+///   "created": { "json": "$.data.user.created_at" }
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtractorRule {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub css: Option<String>,
+    /// Read this attribute of the selected elements instead of their text.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub attr: Option<String>,
+    /// The first capture group is extracted, or the whole match without groups.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub regex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub json: Option<String>,
+    /// Extract every match instead of only the first one.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub all: bool,
+}
+
+/// A compiled extraction rule. Selectors, regexes and JSON paths are
+/// compiled when the manifest is loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "ExtractorRule", into = "ExtractorRule")]
+pub struct Extractor {
+    rule: ExtractorRule,
+    kind: ExtractorKind,
+}
+
+#[derive(Debug, Clone)]
+enum ExtractorKind {
+    Css(Selector),
+    Regex(Regex),
+    Json(JsonPath),
+}
+
+impl TryFrom<ExtractorRule> for Extractor {
+    type Error = ExtractorError;
+
+    fn try_from(rule: ExtractorRule) -> Result<Self, Self::Error> {
+        let kind = match (&rule.css, &rule.regex, &rule.json) {
+            (Some(css), None, None) => ExtractorKind::Css(
+                Selector::parse(css).map_err(|_| ExtractorError::Selector(css.clone()))?,
+            ),
+            (None, Some(regex), None) => ExtractorKind::Regex(Regex::new(regex)?),
+            (None, None, Some(json)) => ExtractorKind::Json(JsonPath::parse(json)?),
+            _ => return Err(ExtractorError::AmbiguousRule),
+        };
+
+        Ok(Self { rule, kind })
+    }
+}
+
+impl From<Extractor> for ExtractorRule {
+    fn from(extractor: Extractor) -> Self {
+        extractor.rule
+    }
+}
+
+/// Run every extractor over a response body.
+///
+/// The body is parsed as HTML and as JSON at most once. Relative URLs read
+/// from `href` and `src` attributes are resolved against `base_url`.
+/// Fields without any value are left out.
+#[must_use]
+pub fn extract_metadata(
+    extractors: &HashMap<String, Extractor>,
+    body: &str,
+    base_url: &Url,
+) -> Metadata {
+    let needs_html = extractors
+        .values()
+        .any(|e| matches!(e.kind, ExtractorKind::Css(_)));
+    let needs_json = extractors
+        .values()
+        .any(|e| matches!(e.kind, ExtractorKind::Json(_)));
+
+    let html = needs_html.then(|| Html::parse_document(body));
+    let json = needs_json
+        .then(|| serde_json::from_str::<Value>(body).ok())
+        .flatten();
+
+    extractors
+        .iter()
+        .filter_map(|(field, extractor)| {
+            let mut values = match (&extractor.kind, &html, &json) {
+                (ExtractorKind::Css(selector), Some(html), _) => html
+                    .select(selector)
+                    .filter_map(|element| match &extractor.rule.attr {
+                        Some(attr) => element
+                            .value()
+                            .attr(attr)
+                            .map(|value| resolve(attr, value, base_url)),
+                        None => Some(collapse_whitespace(&element.text().collect::<String>())),
+                    })
+                    .collect(),
+                (ExtractorKind::Regex(regex), _, _) => regex
+                    .captures_iter(body)
+                    .filter_map(|captures| captures.get(1).or_else(|| captures.get(0)))
+                    .map(|capture| capture.as_str().trim().to_string())
+                    .collect(),
+                (ExtractorKind::Json(path), _, Some(json)) => match path.lookup(json) {
+                    Some(Value::Array(items)) => items.iter().filter_map(json_to_string).collect(),
+                    Some(value) => json_to_string(value).into_iter().collect(),
+                    None => Vec::new(),
+                },
+                _ => Vec::new(),
+            };
+
+            values.retain(|value| !value.is_empty());
+            if !extractor.rule.all {
+                values.truncate(1);
+            }
+
+            (!values.is_empty()).then(|| (field.clone(), values))
+        })
+        .collect()
+}
+
+fn resolve(attr: &str, value: &str, base_url: &Url) -> String {
+    match attr {
+        "href" | "src" => base_url
+            .join(value)
+            .map(|url| url.to_string())
+            .unwrap_or_else(|_| value.to_string()),
+        _ => value.trim().to_string(),
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn json_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extractors(rules: Value) -> HashMap<String, Extractor> {
+        serde_json::from_value(rules).unwrap()
+    }
+
+    #[test]
+    fn test_extract_css_regex_and_json() {
+        let base_url = Url::parse("https://example.com/alice").unwrap();
+        let body = r#"<h1 class="name">  Alice
+            Smith </h1><img class="avatar" src="/a.png"><a rel="me" href="https://x.example/al">x</a>
+            <a rel="me" href="/blog">blog</a><span>1,204 followers</span>"#;
+        let rules = extractors(serde_json::json!({
+            "name": { "css": "h1.name" },
+            "avatar": { "css": "img.avatar", "attr": "src" },
+            "links": { "css": "a[rel=me]", "attr": "href", "all": true },
+            "followers": { "regex": "([0-9,]+) followers" },
+            "bio": { "css": "p.bio" },
+        }));
+
+        let metadata = extract_metadata(&rules, body, &base_url);
+
+        assert_eq!(metadata["name"], vec!["Alice Smith"]);
+        assert_eq!(metadata["avatar"], vec!["https://example.com/a.png"]);
+        assert_eq!(
+            metadata["links"],
+            vec!["https://x.example/al", "https://example.com/blog"]
+        );
+        assert_eq!(metadata["followers"], vec!["1,204"]);
+        assert!(!metadata.contains_key("bio"));
+
+        let rules = extractors(serde_json::json!({
+            "created": { "json": "$.user.created_at" },
+            "followers": { "json": "$.user.followers" },
+        }));
+        let body = r#"{"user": {"created_at": "2019-04-01", "followers": 12}}"#;
+        let metadata = extract_metadata(&rules, body, &base_url);
+
+        assert_eq!(metadata["created"], vec!["2019-04-01"]);
+        assert_eq!(metadata["followers"], vec!["12"]);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let invalid = [
+            serde_json::json!({ "css": "h1", "regex": "x" }),
+            serde_json::json!({ "attr": "href" }),
+            serde_json::json!({ "css": "h1[" }),
+            serde_json::json!({ "json": "user.name" }),
+        ];
+
+        for rule in invalid {
+            assert!(serde_json::from_value::<Extractor>(rule).is_err());
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum JsonPathError {
+    #[error("JSON path '{0}' must start with '$'")]
+    MissingRoot(String),
+    #[error("JSON path '{path}' has an invalid segment at byte {position}")]
+    InvalidSegment { path: String, position: usize },
+    #[error("JSON condition '{0}' compares against an invalid JSON value")]
+    InvalidValue(String),
+}
+
+/// One step into a JSON document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// Object member, written as `.name` or `["name"]`.
+    Key(String),
+    /// Array element, written as `[0]`.
+    Index(usize),
+}
+
+/// A path into a JSON document such as `$.data.user` or `$.errors[0].code`.
+///
+/// Paths are parsed when the manifest is loaded so a malformed path is
+/// reported up front.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct JsonPath {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl JsonPath {
+    ///
+    /// Parses a JSON path.
+    ///
+    /// # Arguments
+    /// * `path` - The path, starting at the document root `$`.
+    ///
+    /// # Returns
+    /// The parsed path.
+    ///
+    /// # Example
+    /// ```
+    /// use serde_json::json;
+    /// use sherlock::json_path::JsonPath;
+    ///
+    /// let path = JsonPath::parse("$.errors[0].code").unwrap();
+    /// let document = json!({"errors": [{"code": 404}]});
+    ///
+    /// assert_eq!(path.lookup(&document), Some(&json!(404)));
+    /// ```
+    pub fn parse(path: &str) -> Result<Self, JsonPathError> {
+        let rest = path
+            .trim()
+            .strip_prefix('$')
+            .ok_or_else(|| JsonPathError::MissingRoot(path.to_string()))?;
+
+        let invalid = |position: usize| JsonPathError::InvalidSegment {
+            path: path.to_string(),
+            position,
+        };
+
+        let mut segments = Vec::new();
+        let mut chars = rest.char_indices().peekable();
+        while let Some((position, c)) = chars.next() {
+            match c {
+                '.' => {
+                    let mut key = String::new();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| *c != '.' && *c != '[') {
+                        key.push(c);
+                    }
+                    if key.is_empty() {
+                        return Err(invalid(position));
+                    }
+                    segments.push(Segment::Key(key));
+                }
+                '[' => {
+                    let mut inner = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, ']')) => break,
+                            Some((_, c)) => inner.push(c),
+                            None => return Err(invalid(position)),
+                        }
+                    }
+
+                    let quoted = inner
+                        .strip_prefix('"')
+                        .and_then(|s| s.strip_suffix('"'))
+                        .or_else(|| inner.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')));
+                    let segment = match quoted {
+                        Some(key) => Segment::Key(key.to_string()),
+                        None => {
+                            Segment::Index(inner.trim().parse().map_err(|_| invalid(position))?)
+                        }
+                    };
+                    segments.push(segment);
+                }
+                _ => return Err(invalid(position)),
+            }
+        }
+
+        Ok(Self {
+            source: path.trim().to_string(),
+            segments,
+        })
+    }
+
+    /// Follow the path through `document`, returning the value it points at.
+    #[must_use]
+    pub fn lookup<'a>(&self, document: &'a Value) -> Option<&'a Value> {
+        self.segments
+            .iter()
+            .try_fold(document, |value, segment| match segment {
+                Segment::Key(key) => value.get(key),
+                Segment::Index(index) => value.get(index),
+            })
+    }
+
+    #[must_use]
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+}
+
+impl TryFrom<String> for JsonPath {
+    type Error = JsonPathError;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        JsonPath::parse(&path)
+    }
+}
+
+impl Serialize for JsonPath {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+}
+
+/// A condition on a JSON document such as `$.data.user == null` or `$.errors[0].code == 404`.
+///
+/// A bare path like `$.data.user.id` holds when the field exists and isn't `null`.
+/// Values compared against are written as JSON, so strings need double quotes.
+/// A missing field compares equal to `null`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct JsonCondition {
+    source: String,
+    path: JsonPath,
+    comparison: Option<(Comparison, Value)>,
+}
+
+impl JsonCondition {
+    ///
+    /// Parses a JSON condition.
+    ///
+    /// # Arguments
+    /// * `condition` - A JSON path, optionally followed by `==` or `!=` and a JSON value.
+    ///
+    /// # Returns
+    /// The parsed condition.
+    ///
+    /// # Example
+    /// ```
+    /// use serde_json::json;
+    /// use sherlock::json_path::JsonCondition;
+    ///
+    /// let condition = JsonCondition::parse("$.errors[0].code == 404").unwrap();
+    ///
+    /// assert!(condition.evaluate(&json!({"errors": [{"code": 404}]})));
+    /// assert!(!condition.evaluate(&json!({"errors": []})));
+    /// ```
+    pub fn parse(condition: &str) -> Result<Self, JsonPathError> {
+        let (path, comparison) = match find_operator(condition) {
+            Some((index, comparison)) => {
+                let literal = condition[index + 2..].trim();
+                let value = serde_json::from_str(literal)
+                    .map_err(|_| JsonPathError::InvalidValue(condition.to_string()))?;
+                (&condition[..index], Some((comparison, value)))
+            }
+            None => (condition, None),
+        };
+
+        Ok(Self {
+            source: condition.trim().to_string(),
+            path: JsonPath::parse(path)?,
+            comparison,
+        })
+    }
+
+    /// Whether the condition holds for `document`.
+    #[must_use]
+    pub fn evaluate(&self, document: &Value) -> bool {
+        let value = self.path.lookup(document);
+        match &self.comparison {
+            None => value.is_some_and(|value| !value.is_null()),
+            Some((comparison, expected)) => {
+                let is_equal = value.unwrap_or(&Value::Null) == expected;
+                match comparison {
+                    Comparison::Equal => is_equal,
+                    Comparison::NotEqual => !is_equal,
+                }
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &JsonPath {
+        &self.path
+    }
+}
+
+/// Find the first `==` or `!=` that isn't inside a quoted key.
+fn find_operator(condition: &str) -> Option<(usize, Comparison)> {
+    let bytes = condition.as_bytes();
+    let mut quote = None;
+    for (index, &byte) in bytes.iter().enumerate() {
+        match (quote, byte) {
+            (None, b'"' | b'\'') => quote = Some(byte),
+            (Some(open), _) if open == byte => quote = None,
+            (None, b'=' | b'!') if bytes.get(index + 1) == Some(&b'=') => {
+                let comparison = match byte {
+                    b'=' => Comparison::Equal,
+                    _ => Comparison::NotEqual,
+                };
+                return Some((index, comparison));
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+impl TryFrom<String> for JsonCondition {
+    type Error = JsonPathError;
+
+    fn try_from(condition: String) -> Result<Self, Self::Error> {
+        JsonCondition::parse(&condition)
+    }
+}
+
+impl Serialize for JsonCondition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl fmt::Display for JsonCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_segments() {
+        let path = JsonPath::parse(r#"$.data["user name"].items[2]"#).unwrap();
+        assert_eq!(
+            path.segments(),
+            &[
+                Segment::Key(String::from("data")),
+                Segment::Key(String::from("user name")),
+                Segment::Key(String::from("items")),
+                Segment::Index(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(matches!(
+            JsonPath::parse("data.user"),
+            Err(JsonPathError::MissingRoot(_))
+        ));
+        assert!(JsonPath::parse("$.data.").is_err());
+        assert!(JsonPath::parse("$.items[x]").is_err());
+        assert!(JsonPath::parse("$.items[0").is_err());
+    }
+
+    #[test]
+    fn test_lookup() {
+        let document = json!({"data": {"user": null, "items": [1, 2]}});
+        let root = JsonPath::parse("$").unwrap();
+        assert_eq!(root.lookup(&document), Some(&document));
+        assert_eq!(
+            JsonPath::parse("$.data.user").unwrap().lookup(&document),
+            Some(&Value::Null)
+        );
+        assert_eq!(
+            JsonPath::parse("$.data.items[1]")
+                .unwrap()
+                .lookup(&document),
+            Some(&json!(2))
+        );
+        assert_eq!(
+            JsonPath::parse("$.data.missing").unwrap().lookup(&document),
+            None
+        );
+    }
+
+    #[test]
+    fn test_condition_comparisons() {
+        let document = json!({"data": {"user": null, "name": "alice"}, "errors": [{"code": 404}]});
+        let holds = |condition: &str| JsonCondition::parse(condition).unwrap().evaluate(&document);
+
+        assert!(holds("$.data.user == null"));
+        assert!(holds("$.data.missing == null"));
+        assert!(holds("$.errors[0].code == 404"));
+        assert!(holds(r#"$.data.name == "alice""#));
+        assert!(holds(r#"$.data.name != "bob""#));
+        assert!(holds(r#"$["data"]["name"]"#));
+        assert!(!holds("$.data.user"));
+        assert!(!holds("$.errors[0].code != 404"));
+    }
+
+    #[test]
+    fn test_condition_invalid() {
+        assert!(matches!(
+            JsonCondition::parse("$.data.user == nil"),
+            Err(JsonPathError::InvalidValue(_))
+        ));
+        assert!(JsonCondition::parse("data == null").is_err());
+    }
+}
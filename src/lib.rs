@@ -1,9 +1,24 @@
+pub mod cassette;
 pub mod checker;
+pub mod confidence;
+pub mod cookies;
+pub mod credentials;
+pub mod discovery;
+pub mod dns;
+pub mod dump;
+pub mod extract;
 pub mod get_data;
+pub mod json_path;
 pub mod output;
+pub mod progress;
+pub mod proxy_pool;
 pub mod query;
 pub mod requests;
+pub mod routing;
 pub mod sherlock_target_manifest;
 pub mod site_info;
+pub mod tor;
+pub mod transport;
+pub mod user_agent;
 pub mod utils;
 pub mod waf;
@@ -1,14 +1,33 @@
 use clap::Parser;
 use color_eyre::Result;
+use reqwest::Url;
 use sherlock::{
+    cassette::{Cassette, CassetteMode},
     checker::{check_username, CheckOptions},
+    confidence::load_site_reliability,
+    cookies::CookieJars,
+    credentials::Credentials,
+    discovery::Discovery,
+    dns::{parse_doh_url, parse_nameserver, parse_override, warn_local_resolution, Nameservers},
+    dump::DumpOptions,
     get_data::{get_default_data, get_json_data},
-    output::{save_results, SaveOptions},
+    output::{print_links, save_results, write_graph, SaveOptions},
+    proxy_pool::{ProxyPool, Rotation},
+    routing::Routing,
     sherlock_target_manifest::{SherlockTargetManifest, TargetInfo},
+    tor::{Tor, TorOptions},
+    transport::{HttpVersion, Transport},
+    user_agent::{UserAgentStrategy, UserAgents},
     utils::create_username_variants,
+    waf::WafDatabase,
 };
+use std::io::IsTerminal;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
+use tracing::{warn, Level};
+use tracing_subscriber::{filter::Targets, fmt, prelude::*};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -33,6 +52,10 @@ struct Cli {
     #[clap(short = 'f', long, alias = "output-folder")]
     output_folder: Option<String>,
 
+    /// Don't write the default text file. Other output files are only created when requested.
+    #[clap(long, conflicts_with = "output_file")]
+    no_txt: bool,
+
     /// Create Comma-Separated Values (CSV) File.
     #[clap(short, long, alias = "csv")]
     csv: bool,
@@ -41,6 +64,14 @@ struct Cli {
     #[clap(long)]
     xlsx: bool,
 
+    /// Create a JSON report including any extracted profile metadata.
+    #[clap(long)]
+    json_report: bool,
+
+    /// Create an HTML report including any extracted profile metadata.
+    #[clap(long)]
+    html: bool,
+
     /// Limit analysis to just the listed sites. Add multiple options to specify more than one site.
     #[clap(short, long)]
     site_list: Vec<String>,
@@ -49,10 +80,119 @@ struct Cli {
     #[clap(short, long, alias = "proxy")]
     proxy: Option<String>,
 
-    /// Dump the HTTP request to stdout for targeted debugging.
+    /// File with one proxy URL per line (http, https, socks5 or socks5h) to spread requests across.
+    #[clap(long, conflicts_with = "proxy")]
+    proxy_file: Option<String>,
+
+    /// How requests are assigned to the proxies in the proxy file.
+    #[clap(long, value_enum, default_value_t = Rotation::RoundRobin, requires = "proxy_file")]
+    proxy_rotation: Rotation,
+
+    /// Remove a proxy from the pool after this many failed or blocked requests in a row.
+    #[clap(
+        long,
+        default_value_t = 3,
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "proxy_file"
+    )]
+    proxy_max_failures: u32,
+
+    /// JSON file with user-agent profiles to use instead of the bundled ones.
+    #[clap(long)]
+    user_agent_file: Option<String>,
+
+    /// How a user-agent profile is chosen for each request.
+    #[clap(long, value_enum, default_value_t = UserAgentStrategy::Random)]
+    user_agent_strategy: UserAgentStrategy,
+
+    /// Send every request with the user-agent profile of this name.
+    #[clap(long, conflicts_with = "user_agent_strategy")]
+    user_agent_profile: Option<String>,
+
+    /// Keep cookies set by each site across redirects and usernames.
+    #[clap(long)]
+    cookies: bool,
+
+    /// Load cookies from a Netscape cookies.txt or JSON file. Implies --cookies. Add multiple options to load more than one file.
+    #[clap(long)]
+    cookie_file: Vec<String>,
+
+    /// JSON file with credentials per site, referenced from the manifest as {credential.bearer}, {credential.apiKey}, {credential.basic} or {credential.cookie}. SHERLOCK_CREDENTIAL_<SITE>_<KIND> environment variables take precedence.
+    #[clap(long)]
+    secrets_file: Option<String>,
+
+    /// JSON file with rules that route sites by name, tag or host to a proxy, a direct connection or skip them.
+    #[clap(long)]
+    routing_file: Option<String>,
+
+    /// HTTP version to make requests with. HTTP/3 needs a build with the http3 feature and cannot be combined with proxies.
+    #[clap(long, value_enum, default_value_t = HttpVersion::Auto)]
+    http_version: HttpVersion,
+
+    /// PEM file with extra root certificates to trust, e.g. the CA of a TLS-inspecting proxy. Add multiple options to load more than one file.
+    #[clap(long)]
+    ca_cert: Vec<String>,
+
+    /// Don't verify TLS certificates for this site. Only use this for sites you control. Add multiple options to specify more than one site.
+    #[clap(long)]
+    insecure_site: Vec<String>,
+
+    /// DNS server to look hostnames up with instead of the system resolver, e.g. 1.1.1.1 or [::1]:5353. Add multiple options to fall back to more servers.
+    #[clap(long, value_parser = parse_nameserver)]
+    dns_server: Vec<SocketAddr>,
+
+    /// Look hostnames up with DNS-over-HTTPS at this URL, e.g. https://1.1.1.1/dns-query. Queries are sent through --proxy or Tor when either is used.
+    #[clap(long, value_parser = parse_doh_url, conflicts_with = "dns_server")]
+    doh: Option<Url>,
+
+    /// Connect to this address for a host instead of looking it up, as host:ip. Add multiple options to specify more than one host.
+    #[clap(long, value_parser = parse_override)]
+    resolve: Vec<(String, IpAddr)>,
+
+    /// Make every request over Tor, failing instead of falling back to a direct connection.
+    #[clap(long, conflicts_with_all = ["proxy", "proxy_file"])]
+    tor: bool,
+
+    /// Address of the Tor SOCKS port.
+    #[clap(long, default_value = "127.0.0.1:9050", requires = "tor")]
+    tor_socks: String,
+
+    /// Address of the Tor control port. Enables new circuits when a site blocks or rate limits requests.
+    #[clap(long, requires = "tor")]
+    tor_control: Option<String>,
+
+    /// Password for the Tor control port.
+    #[clap(long, requires = "tor_control")]
+    tor_control_password: Option<String>,
+
+    /// Use a separate Tor circuit for each site.
+    #[clap(long, requires = "tor")]
+    tor_isolate: bool,
+
+    /// Dump each HTTP request and response to a file per site for targeted debugging.
     #[clap(short, long)]
     dump_response: bool,
 
+    /// Directory response dumps are written to, one subdirectory per username.
+    #[clap(long, default_value = "dumps")]
+    dump_dir: PathBuf,
+
+    /// Only dump responses for these sites. Add multiple options to specify more than one site.
+    #[clap(long, requires = "dump_response")]
+    dump_site: Vec<String>,
+
+    /// Only dump responses with these HTTP status codes. Add multiple options to specify more than one code.
+    #[clap(long, requires = "dump_response")]
+    dump_status: Vec<u16>,
+
+    /// Record every HTTP exchange to this cassette file for later replay.
+    #[clap(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Replay HTTP exchanges from a recorded cassette file instead of using the network.
+    #[clap(long)]
+    replay: Option<PathBuf>,
+
     /// Load data from a JSON file or an online, valid, JSON file.
     #[clap(
         short,
@@ -66,6 +206,14 @@ struct Cli {
     #[clap(short, long, alias = "timeout", default_value_t = 60.0)]
     timeout: f64,
 
+    /// Retry a request this many times when it times out or no connection can be made.
+    #[clap(long, default_value_t = 0)]
+    retries: u32,
+
+    /// Maximum number of bytes to read from each response body.
+    #[clap(long, default_value_t = 2 * 1024 * 1024)]
+    max_body_size: usize,
+
     /// Output sites where the username was not found.
     #[clap(long, alias = "print-all")]
     print_all: bool,
@@ -74,6 +222,42 @@ struct Cli {
     #[clap(long, alias = "print-found", default_value_t = true)]
     print_found: bool,
 
+    /// Show a live progress line with hits, errors and an ETA while checking.
+    #[clap(long)]
+    progress: bool,
+
+    /// Don't color terminal output.
+    #[clap(short, long)]
+    no_color: bool,
+
+    /// Also check usernames linked from claimed profiles, using the manifest's extract rules.
+    #[clap(short, long)]
+    recursive: bool,
+
+    /// How many links away from the given usernames to follow in recursive mode.
+    #[clap(long, default_value_t = 2, requires = "recursive")]
+    max_depth: usize,
+
+    /// Maximum number of usernames to check in recursive mode, including the given ones.
+    #[clap(long, default_value_t = 20, requires = "recursive")]
+    max_usernames: usize,
+
+    /// Save the graph of linked usernames found in recursive mode to this JSON file.
+    #[clap(long, requires = "recursive")]
+    graph_file: Option<String>,
+
+    /// Hide claimed results with a confidence score (0 to 1) below this threshold.
+    #[clap(long, default_value_t = 0.0)]
+    min_confidence: f32,
+
+    /// JSON file with the fraction of self-tests each site passed, used to weight confidence scores.
+    #[clap(long)]
+    site_history: Option<String>,
+
+    /// JSON file with extra WAF fingerprints and weaker hints to check responses against, in addition to the bundled ones.
+    #[clap(long)]
+    waf_fingerprints: Vec<String>,
+
     /// Browse to all results on default browser.
     #[clap(short, long, alias = "browse")]
     browse: bool,
@@ -93,6 +277,30 @@ async fn main() -> Result<()> {
 
     let cli = Cli::parse();
 
+    let use_color =
+        !cli.no_color && std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal();
+    if !use_color {
+        colored::control::set_override(false);
+    }
+
+    let log_level = match cli.verbose {
+        true => Level::DEBUG,
+        false => Level::WARN,
+    };
+    tracing_subscriber::registry()
+        .with(
+            fmt::layer()
+                .with_target(false)
+                .with_ansi(use_color)
+                .with_writer(std::io::stderr),
+        )
+        .with(
+            Targets::new()
+                .with_target("sherlock", log_level)
+                .with_default(Level::WARN),
+        )
+        .init();
+
     let json_str = match cli.local {
         true => get_default_data(),
         false => get_json_data(cli.json_file).await?,
@@ -130,28 +338,184 @@ async fn main() -> Result<()> {
 
     let username_variants = create_username_variants(&cli.usernames);
 
+    let cassette = match (cli.record, cli.replay) {
+        (Some(path), _) => Some(Arc::new(Cassette::record(path, cli.max_body_size))),
+        (None, Some(path)) => Some(Arc::new(Cassette::replay(path)?)),
+        (None, None) => None,
+    };
+
+    let site_reliability = cli
+        .site_history
+        .as_deref()
+        .map(load_site_reliability)
+        .transpose()?;
+
+    let mut waf = WafDatabase::default();
+    for path in &cli.waf_fingerprints {
+        waf.load(path)?;
+    }
+
+    let tor = match cli.tor {
+        true => {
+            let tor = Tor::new(TorOptions {
+                socks: cli.tor_socks,
+                control: cli.tor_control,
+                control_password: cli.tor_control_password,
+                isolate: cli.tor_isolate,
+            });
+            tor.check().await?;
+            Some(Arc::new(tor))
+        }
+        false => None,
+    };
+
+    let proxy_pool = cli
+        .proxy_file
+        .as_deref()
+        .map(|path| ProxyPool::load(path, cli.proxy_rotation, cli.proxy_max_failures))
+        .transpose()?
+        .map(Arc::new);
+
+    let routing = cli.routing_file.as_deref().map(Routing::load).transpose()?;
+    if let (Some(routing), true) = (&routing, cli.tor) {
+        routing.require_tor()?;
+    }
+
+    let mut user_agents = UserAgents::default();
+    if let Some(path) = &cli.user_agent_file {
+        user_agents.load(path)?;
+    }
+    user_agents.set_strategy(cli.user_agent_strategy);
+    if let Some(name) = &cli.user_agent_profile {
+        user_agents.fix(name)?;
+    }
+
+    let cookies = match cli.cookies || !cli.cookie_file.is_empty() {
+        true => {
+            let mut cookies = CookieJars::default();
+            for path in &cli.cookie_file {
+                cookies.load(path)?;
+            }
+            Some(Arc::new(cookies))
+        }
+        false => None,
+    };
+
+    let credentials = match &cli.secrets_file {
+        Some(path) => Credentials::load(path)?,
+        None => Credentials::default(),
+    };
+
+    let mut transport = Transport::new(cli.http_version);
+    for path in &cli.ca_cert {
+        transport.load_root_certificates(path)?;
+    }
+    transport.set_insecure(cli.insecure_site);
+    // DNS-over-HTTPS goes the same way as the requests, so lookups don't leak past the proxy
+    let dns_proxy = match &tor {
+        Some(tor) => Some(tor.proxy_url("DNS-over-HTTPS")?),
+        None => cli.proxy.clone(),
+    };
+    if let Some(url) = cli.doh {
+        transport.set_nameservers(Nameservers::Https(url), dns_proxy.as_deref())?;
+    } else if !cli.dns_server.is_empty() {
+        transport.set_nameservers(Nameservers::Udp(cli.dns_server), None)?;
+    }
+    for (host, ip) in cli.resolve {
+        transport.add_override(host, ip);
+    }
+    if let Some(proxy) = &cli.proxy {
+        warn_local_resolution(proxy);
+    }
+    if !transport.insecure_sites().is_empty() {
+        let mut sites = transport.insecure_sites().iter().collect::<Vec<_>>();
+        sites.sort();
+        warn!(
+            ?sites,
+            "TLS certificate verification is DISABLED for these sites; their responses can be intercepted or forged"
+        );
+    }
+
     let check_options = CheckOptions {
         timeout: Duration::from_secs_f64(cli.timeout),
         proxy: cli.proxy.map(Arc::from),
         print_all: cli.print_all,
         print_found: cli.print_found,
-        dump_response: cli.dump_response,
+        dump: cli.dump_response.then_some(DumpOptions {
+            dir: cli.dump_dir,
+            sites: cli.dump_site,
+            statuses: cli.dump_status,
+        }),
         browse: cli.browse,
+        progress: cli.progress,
+        cassette: cassette.clone(),
+        min_confidence: cli.min_confidence,
+        site_reliability: site_reliability.map(Arc::new),
+        waf: Arc::new(waf),
+        max_body_size: cli.max_body_size,
+        tor,
+        proxy_pool: proxy_pool.clone(),
+        routing: routing.map(Arc::new),
+        user_agents: Arc::new(user_agents),
+        cookies,
+        credentials: Arc::new(credentials),
+        transport: Arc::new(transport),
+        retries: cli.retries,
     };
 
     let save_options = SaveOptions {
         output_file: cli.output_file,
         output_folder: cli.output_folder,
+        no_txt: cli.no_txt,
         csv: cli.csv,
         xlsx: cli.xlsx,
+        json: cli.json_report,
+        html: cli.html,
         print_all: cli.print_all,
         print_found: cli.print_found,
+        min_confidence: cli.min_confidence,
     };
 
-    for username in username_variants {
-        let results = check_username(&username, Arc::clone(&arc_targets), &check_options).await?;
-        save_results(&username, &results, &save_options)?;
+    let run = async {
+        if !cli.recursive {
+            for username in username_variants {
+                let results =
+                    check_username(&username, Arc::clone(&arc_targets), &check_options).await?;
+                save_results(&username, &results, &save_options)?;
+            }
+            return Ok(());
+        }
+
+        let mut discovery = Discovery::new(
+            username_variants,
+            &arc_targets,
+            cli.max_depth,
+            cli.max_usernames,
+        );
+        while let Some((username, depth)) = discovery.next_username() {
+            let results =
+                check_username(&username, Arc::clone(&arc_targets), &check_options).await?;
+            save_results(&username, &results, &save_options)?;
+            discovery.record(depth, &results);
+        }
+
+        print_links(discovery.links());
+        if let Some(graph_file) = &cli.graph_file {
+            write_graph(graph_file, discovery.links())?;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    if let Some(proxy_pool) = &proxy_pool {
+        proxy_pool.log_stats();
+    }
+
+    // keep whatever was recorded, even when the run failed part way
+    if let Some(cassette) = cassette.filter(|c| c.mode() == CassetteMode::Record) {
+        cassette.save()?;
     }
 
-    Ok(())
+    run
 }
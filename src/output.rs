@@ -1,21 +1,29 @@
+use crate::discovery::Link;
+use crate::extract::Metadata;
 use crate::query::{QueryResult, QueryStatus};
+use crate::utils::write_atomic;
 use color_eyre::Result;
 use colored::Colorize;
+use serde::Serialize;
 
 #[cfg(feature = "xlsx")]
 use rust_xlsxwriter::Workbook;
 
-use std::fs::File;
 use std::io::Write;
 
 /// Options for saving results
 pub struct SaveOptions {
     pub output_file: Option<String>,
     pub output_folder: Option<String>,
+    pub no_txt: bool,
     pub csv: bool,
     pub xlsx: bool,
+    pub json: bool,
+    pub html: bool,
     pub print_all: bool,
     pub print_found: bool,
+    /// Leave out claimed results with a lower confidence than this.
+    pub min_confidence: f32,
 }
 
 /// Save the results to a file.
@@ -25,18 +33,24 @@ pub struct SaveOptions {
 /// * `results` - The results to save.
 /// * `output_file` - The output file to save the results to.
 /// * `output_folder` - The output folder to save the results to.
+/// * `no_txt` - Skip the default text file unless an output file was given.
 /// * `csv` - Save the results to a CSV file.
 /// * `xlsx` - Save the results to an XLSX file.
+/// * `json` - Save the results to a JSON file.
+/// * `html` - Save the results to an HTML file.
 /// * `print_all` - Print all results.
 /// * `print_found` - Print only found results.
+/// * `min_confidence` - Leave out claimed results below this confidence.
 ///
 /// # Returns
 /// A Result containing the success or failure of the operation.
-pub fn save_results(
-    username: &str,
-    results: &[QueryResult],
-    options: &SaveOptions,
-) -> Result<()> {
+pub fn save_results(username: &str, results: &[QueryResult], options: &SaveOptions) -> Result<()> {
+    let results = &results
+        .iter()
+        .filter(|result| !result.is_low_confidence(options.min_confidence))
+        .cloned()
+        .collect::<Vec<_>>();
+
     let total_hits = results
         .iter()
         .filter(|result| result.status == QueryStatus::Claimed)
@@ -50,24 +64,28 @@ pub fn save_results(
     }
 
     let output_file = match (&options.output_file, &options.output_folder) {
-        (Some(output_file), _) => output_file.to_string(),
-        (None, Some(output_folder)) => format!("{output_folder}/{username}.txt"),
-        (None, None) => format!("{username}.txt"),
+        (Some(output_file), _) => Some(output_file.to_string()),
+        (None, _) if options.no_txt => None,
+        (None, Some(output_folder)) => Some(format!("{output_folder}/{username}.txt")),
+        (None, None) => Some(format!("{username}.txt")),
     };
 
-    let mut file = File::create(&output_file)?;
-    for result in results {
-        if result.status == QueryStatus::Claimed {
-            writeln!(file, "{}", result.site_url_user)?;
-        }
-    }
+    if let Some(output_file) = output_file {
+        write_atomic(&output_file, |file| {
+            for result in results {
+                if result.status == QueryStatus::Claimed {
+                    writeln!(file, "{}", result.site_url_user)?;
+                }
+            }
 
-    writeln!(file, "Total Websites Username Detected On: {total_hits}")?;
+            writeln!(file, "Total Websites Username Detected On: {total_hits}")
+        })?;
+    }
 
     if options.csv {
         write_csv(
             username,
-            &results,
+            results,
             options.output_folder.as_deref(),
             options.print_all,
             options.print_found,
@@ -76,15 +94,218 @@ pub fn save_results(
 
     if options.xlsx {
         #[cfg(feature = "xlsx")]
-        write_xlsx(&username, &results, output_folder, print_all, print_found)?;
+        write_xlsx(
+            username,
+            results,
+            options.output_folder.as_deref(),
+            options.print_all,
+            options.print_found,
+        )?;
 
         #[cfg(not(feature = "xlsx"))]
         eprintln!("Error: xlsx support is not enabled");
     }
 
+    if options.json {
+        write_json(
+            username,
+            results,
+            options.output_folder.as_deref(),
+            options.print_all,
+            options.print_found,
+        )?;
+    }
+
+    if options.html {
+        write_html(
+            username,
+            results,
+            options.output_folder.as_deref(),
+            options.print_all,
+            options.print_found,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// A result as written to the JSON and HTML reports.
+#[derive(Debug, Serialize)]
+struct ReportRow<'a> {
+    username: &'a str,
+    name: &'a str,
+    url_main: &'a str,
+    url_user: &'a str,
+    exists: String,
+    http_status: Option<u16>,
+    response_time_s: f64,
+    confidence: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<&'a str>,
+    metadata: &'a Metadata,
+}
+
+fn report_rows<'a>(
+    username: &'a str,
+    results: &'a [QueryResult],
+    print_all: bool,
+    print_found: bool,
+) -> Vec<ReportRow<'a>> {
+    results
+        .iter()
+        .filter(|result| !(print_found && !print_all && result.status != QueryStatus::Claimed))
+        .map(|result| ReportRow {
+            username,
+            name: &result.site_name,
+            url_main: &result.info.url_main,
+            url_user: &result.site_url_user,
+            exists: format!("{:?}", result.status),
+            http_status: result.http_status,
+            response_time_s: result.query_time.as_secs_f64(),
+            confidence: result.confidence,
+            context: result.context.as_deref(),
+            metadata: &result.metadata,
+        })
+        .collect()
+}
+
+/// Flatten metadata into a single `field=value` list.
+fn format_metadata(metadata: &Metadata) -> String {
+    metadata
+        .iter()
+        .map(|(field, values)| format!("{field}={}", values.join(" | ")))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Write the results to a JSON File.
+/// # Arguments
+/// * `username` - The username to save the results for.
+/// * `results` - The results to save.
+/// * `output_folder` - The output folder to save the results to.
+/// * `print_all` - Print all results.
+/// * `print_found` - Print only found results.
+///
+/// # Returns
+/// A Result containing the success or failure of the operation.
+pub fn write_json(
+    username: &str,
+    results: &[QueryResult],
+    output_folder: Option<&str>,
+    print_all: bool,
+    print_found: bool,
+) -> color_eyre::Result<()> {
+    let json_filename = match output_folder {
+        None => format!("{username}.json"),
+        Some(folder) => format!("{folder}/{username}.json"),
+    };
+
+    let rows = report_rows(username, results, print_all, print_found);
+    let json = serde_json::to_string_pretty(&rows)?;
+    write_atomic(json_filename, |file| writeln!(file, "{json}"))?;
+
+    Ok(())
+}
+
+/// Write the results to an HTML File.
+/// # Arguments
+/// * `username` - The username to save the results for.
+/// * `results` - The results to save.
+/// * `output_folder` - The output folder to save the results to.
+/// * `print_all` - Print all results.
+/// * `print_found` - Print only found results.
+///
+/// # Returns
+/// A Result containing the success or failure of the operation.
+pub fn write_html(
+    username: &str,
+    results: &[QueryResult],
+    output_folder: Option<&str>,
+    print_all: bool,
+    print_found: bool,
+) -> color_eyre::Result<()> {
+    let html_filename = match output_folder {
+        None => format!("{username}.html"),
+        Some(folder) => format!("{folder}/{username}.html"),
+    };
+
+    let rows = report_rows(username, results, print_all, print_found);
+    write_atomic(html_filename, |file| {
+        writeln!(file, "<!DOCTYPE html>")?;
+        writeln!(file, "<html>")?;
+        writeln!(file, "<head>")?;
+        writeln!(file, "<meta charset=\"utf-8\">")?;
+        writeln!(
+            file,
+            "<title>Sherlock results for {}</title>",
+            escape_html(username)
+        )?;
+        writeln!(file, "</head>")?;
+        writeln!(file, "<body>")?;
+        writeln!(
+            file,
+            "<h1>Sherlock results for {}</h1>",
+            escape_html(username)
+        )?;
+        writeln!(file, "<table>")?;
+        writeln!(
+            file,
+            "<tr><th>name</th><th>url_user</th><th>exists</th><th>http_status</th><th>response_time_s</th><th>confidence</th><th>metadata</th></tr>"
+        )?;
+
+        for row in &rows {
+            let metadata = row
+                .metadata
+                .iter()
+                .map(|(field, values)| {
+                    let values = values
+                        .iter()
+                        .map(|value| escape_html(value))
+                        .collect::<Vec<_>>()
+                        .join("<br>");
+                    format!("<dt>{}</dt><dd>{values}</dd>", escape_html(field))
+                })
+                .collect::<String>();
+
+            writeln!(
+                file,
+                "<tr><td><a href=\"{}\">{}</a></td><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td><td>{:.3}</td><td>{:.2}</td><td><dl>{}</dl></td></tr>",
+                escape_html(row.url_main),
+                escape_html(row.name),
+                escape_html(row.url_user),
+                escape_html(row.url_user),
+                row.exists,
+                row.http_status.map(|code| code.to_string()).unwrap_or_default(),
+                row.response_time_s,
+                row.confidence,
+                metadata,
+            )?;
+        }
+
+        writeln!(file, "</table>")?;
+        writeln!(file, "</body>")?;
+        writeln!(file, "</html>")
+    })?;
+
     Ok(())
 }
 
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
 /// Write the results to a Excel File.
 /// # Arguments
 /// * `username` - The username to save the results for.
@@ -98,7 +319,7 @@ pub fn save_results(
 #[cfg(feature = "xlsx")]
 pub fn write_xlsx(
     username: &str,
-    results: &Vec<QueryResult>,
+    results: &[QueryResult],
     output_folder: Option<&str>,
     print_all: bool,
     print_found: bool,
@@ -114,6 +335,8 @@ pub fn write_xlsx(
     worksheet.write_string(0, 4, "exists")?;
     worksheet.write_string(0, 5, "http_status")?;
     worksheet.write_string(0, 6, "response_time_s")?;
+    worksheet.write_string(0, 7, "metadata")?;
+    worksheet.write_string(0, 8, "confidence")?;
 
     let mut row = 1;
     for result in results {
@@ -124,12 +347,14 @@ pub fn write_xlsx(
         let response_time_s = result.query_time.as_secs();
 
         worksheet.write_string(row, 0, username)?;
-        worksheet.write_string(row, 1, &result.site_name)?;
-        worksheet.write_string(row, 2, &result.url_main)?;
+        worksheet.write_string(row, 1, &*result.site_name)?;
+        worksheet.write_string(row, 2, &result.info.url_main)?;
         worksheet.write_string(row, 3, &result.site_url_user)?;
-        worksheet.write_string(row, 4, &format!("{:?}", result.status))?;
+        worksheet.write_string(row, 4, format!("{:?}", result.status))?;
         worksheet.write_number(row, 5, result.http_status.unwrap_or(0) as f64)?;
         worksheet.write_number(row, 6, response_time_s as f64)?;
+        worksheet.write_string(row, 7, format_metadata(&result.metadata))?;
+        worksheet.write_number(row, 8, result.confidence)?;
 
         row += 1;
     }
@@ -139,7 +364,8 @@ pub fn write_xlsx(
         Some(folder) => format!("{}/{}.xlsx", folder, username),
     };
 
-    workbook.save(xlsx_filename)?;
+    let buffer = workbook.save_to_buffer()?;
+    write_atomic(xlsx_filename, |file| file.write_all(&buffer))?;
 
     Ok(())
 }
@@ -166,34 +392,38 @@ pub fn write_csv(
         Some(folder) => format!("{folder}/{username}.csv"),
     };
 
-    let mut csv_report = File::create(csv_filename)?;
+    write_atomic(csv_filename, |csv_report| {
+        // Write the CSV header
+        writeln!(
+            csv_report,
+            "username,name,url_main,url_user,exists,http_status,response_time_s,metadata,confidence"
+        )?;
 
-    // Write the CSV header
-    writeln!(
-        csv_report,
-        "username,name,url_main,url_user,exists,http_status,response_time_s"
-    )?;
+        // Iterate over the results and write each row
+        for result in results {
+            if print_found && !print_all && result.status != QueryStatus::Claimed {
+                continue;
+            }
 
-    // Iterate over the results and write each row
-    for result in results {
-        if print_found && !print_all && result.status != QueryStatus::Claimed {
-            continue;
-        }
+            let response_time_s = result.query_time.as_secs();
 
-        let response_time_s = result.query_time.as_secs();
+            writeln!(
+                csv_report,
+                "{},{},{},{},{:?},{},{},{},{:.2}",
+                username,
+                result.site_name,
+                result.info.url_main,
+                result.site_url_user,
+                result.status,
+                result.http_status.as_ref().unwrap_or(&0),
+                response_time_s,
+                escape_csv(&format_metadata(&result.metadata)),
+                result.confidence
+            )?;
+        }
 
-        writeln!(
-            csv_report,
-            "{},{},{},{},{:?},{},{}",
-            username,
-            result.site_name,
-            result.info.url_main,
-            result.site_url_user,
-            result.status,
-            result.http_status.as_ref().unwrap_or(&0),
-            response_time_s
-        )?;
-    }
+        Ok(())
+    })?;
 
     Ok(())
 }
@@ -206,14 +436,16 @@ pub fn print_result(result: &QueryResult) {
     let response_time_text = format!("[{}ms]", result.query_time.as_millis());
     match result.status {
         QueryStatus::Claimed => {
+            let confidence_text = format!("({:.0}%)", result.confidence * 100.0);
             println!(
-                "{}{}{} {} {}: {}",
+                "{}{}{} {} {}: {} {}",
                 "[".white(),
                 "+".green(),
                 "]".white(),
                 response_time_text.white(),
                 result.site_name.green(),
                 result.site_url_user,
+                confidence_text.white(),
             );
         }
         QueryStatus::Available => {
@@ -254,15 +486,60 @@ pub fn print_result(result: &QueryResult) {
             );
         }
         QueryStatus::Waf => {
+            let blocked = match &result.context {
+                Some(vendor) => format!("Blocked by bot detection ({vendor})"),
+                None => "Blocked by bot detection".to_string(),
+            };
             println!(
                 "{}{}{} {} {} {}",
                 "[".white(),
                 "-".red(),
                 "]".white(),
                 result.site_name.green(),
-                "Blocked by bot detection".red(),
+                blocked.red(),
                 "(proxy may help)".yellow(),
             );
         }
     };
 }
+
+/// Print the graph of linked usernames found in recursive mode.
+///
+/// # Arguments
+/// * `links` - The links found on claimed profiles.
+pub fn print_links(links: &[Link]) {
+    if links.is_empty() {
+        return;
+    }
+
+    println!("{}", "Linked usernames:".white());
+    for link in links {
+        let target = match &link.linked_site {
+            Some(site) => format!("{} ({})", link.linked_username, site),
+            None => link.linked_username.clone(),
+        };
+        println!(
+            "{} {} {} {} {}",
+            link.username.green(),
+            "->".white(),
+            link.site.green(),
+            "->".white(),
+            target.yellow(),
+        );
+    }
+}
+
+/// Write the graph of linked usernames found in recursive mode to a JSON file.
+///
+/// # Arguments
+/// * `graph_file` - The file to save the graph to.
+/// * `links` - The links found on claimed profiles.
+///
+/// # Returns
+/// A Result containing the success or failure of the operation.
+pub fn write_graph(graph_file: &str, links: &[Link]) -> Result<()> {
+    let json = serde_json::to_string_pretty(links)?;
+    write_atomic(graph_file, |file| writeln!(file, "{json}"))?;
+
+    Ok(())
+}
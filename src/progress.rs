@@ -0,0 +1,183 @@
+use crate::query::{QueryResult, QueryStatus};
+use std::io::{self, IsTerminal, Stdout, Write};
+use std::time::{Duration, Instant};
+
+/// Number of plain progress lines printed over a whole scan when stdout
+/// is not a terminal.
+const PLAIN_STEPS: usize = 10;
+
+/// Live progress of a scan, fed with every result read from the checker's channel.
+///
+/// On a terminal the progress is a single status line that is redrawn in place.
+/// Otherwise a plain line is printed every tenth of the scan.
+#[derive(Debug)]
+pub struct Progress<W: Write = Stdout> {
+    total: usize,
+    completed: usize,
+    claimed: usize,
+    errors: usize,
+    waf: usize,
+    start: Instant,
+    is_tty: bool,
+    last_step: usize,
+    out: W,
+}
+
+impl Progress {
+    /// Progress written to stdout, redrawn in place when it is a terminal.
+    #[must_use]
+    pub fn new(total: usize) -> Self {
+        let out = io::stdout();
+        let is_tty = out.is_terminal();
+        Self::with_writer(total, out, is_tty)
+    }
+}
+
+impl<W: Write> Progress<W> {
+    #[must_use]
+    pub fn with_writer(total: usize, out: W, is_tty: bool) -> Self {
+        Self {
+            total,
+            completed: 0,
+            claimed: 0,
+            errors: 0,
+            waf: 0,
+            start: Instant::now(),
+            is_tty,
+            last_step: 0,
+            out,
+        }
+    }
+
+    /// Record a finished site check.
+    pub fn record(&mut self, result: &QueryResult) {
+        self.completed += 1;
+        match result.status {
+            QueryStatus::Claimed => self.claimed += 1,
+            QueryStatus::Unknown => self.errors += 1,
+            QueryStatus::Waf => self.waf += 1,
+            QueryStatus::Available | QueryStatus::Illegal => {}
+        }
+    }
+
+    /// Estimated time until every site has been checked.
+    #[must_use]
+    pub fn eta(&self) -> Option<Duration> {
+        if self.completed == 0 {
+            return None;
+        }
+
+        let remaining = self.total.saturating_sub(self.completed) as u32;
+        Some(self.start.elapsed() / self.completed as u32 * remaining)
+    }
+
+    /// Erase the status line so a result can be printed in its place.
+    pub fn clear(&mut self) {
+        if self.is_tty {
+            let _ = write!(self.out, "\r\x1b[2K");
+            let _ = self.out.flush();
+        }
+    }
+
+    /// Draw the status line, or print a plain line when stdout is not a terminal.
+    pub fn draw(&mut self) {
+        if self.is_tty {
+            let summary = self.summary();
+            let _ = write!(self.out, "{summary}");
+            let _ = self.out.flush();
+            return;
+        }
+
+        let step = self.completed * PLAIN_STEPS / self.total.max(1);
+        if step > self.last_step {
+            self.last_step = step;
+            let summary = self.summary();
+            let _ = writeln!(self.out, "{summary}");
+        }
+    }
+
+    /// Erase the status line once the scan has completed.
+    pub fn finish(&mut self) {
+        self.clear();
+    }
+
+    fn summary(&self) -> String {
+        let eta = match self.eta() {
+            Some(eta) => format!("{}s", eta.as_secs()),
+            None => String::from("?"),
+        };
+
+        format!(
+            "[{}/{}] claimed: {} errors: {} waf: {} eta: {}",
+            self.completed, self.total, self.claimed, self.errors, self.waf, eta
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::Metadata;
+    use crate::sherlock_target_manifest::TargetInfo;
+    use std::sync::Arc;
+
+    fn result(status: QueryStatus) -> QueryResult {
+        let info: TargetInfo = serde_json::from_value(serde_json::json!({
+            "url": "https://example.com/{}",
+            "urlMain": "https://example.com/",
+            "username_claimed": "blue",
+            "errorType": "status_code",
+        }))
+        .unwrap();
+
+        QueryResult {
+            username: Arc::from("alice"),
+            site_name: Arc::from("Example"),
+            info: Arc::new(info),
+            site_url_user: String::new(),
+            status,
+            http_status: None,
+            query_time: Duration::ZERO,
+            context: None,
+            metadata: Metadata::new(),
+            confidence: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_plain_output_every_tenth() {
+        let mut progress = Progress::with_writer(20, Vec::new(), false);
+        let statuses = [QueryStatus::Claimed, QueryStatus::Unknown, QueryStatus::Waf];
+        for index in 0..20 {
+            progress.record(&result(
+                statuses
+                    .get(index)
+                    .copied()
+                    .unwrap_or(QueryStatus::Available),
+            ));
+            progress.draw();
+        }
+        progress.finish();
+
+        let out = String::from_utf8(progress.out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 10);
+        assert!(lines[0].starts_with("[2/20] claimed: 1 errors: 1 waf: 0 eta: "));
+        assert!(lines[9].starts_with("[20/20] claimed: 1 errors: 1 waf: 1 eta: 0s"));
+        assert!(!out.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_tty_output_is_redrawn() {
+        let mut progress = Progress::with_writer(3, Vec::new(), true);
+        progress.record(&result(QueryStatus::Claimed));
+        progress.draw();
+        progress.clear();
+        progress.finish();
+
+        let out = String::from_utf8(progress.out).unwrap();
+        assert!(out.starts_with("[1/3] claimed: 1 errors: 0 waf: 0 eta: "));
+        assert!(out.ends_with("\r\x1b[2K\r\x1b[2K"));
+        assert!(!out.contains('\n'));
+    }
+}
@@ -1,26 +1,58 @@
 use fancy_regex::Regex;
+use reqwest::cookie::Jar;
+use reqwest::header::HeaderValue;
 use reqwest::Response;
+use serde_json::Value;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{fmt, time::Instant};
 use thiserror::Error;
 use tokio::sync::mpsc::Sender;
+use tracing::debug;
 
-use crate::requests::{make_request, RequestResult};
+use crate::cassette::Cassette;
+use crate::credentials::{has_placeholder, MissingCredential, SiteCredentials};
+use crate::extract::extract_metadata;
+use crate::extract::Metadata;
+use crate::proxy_pool::{ProxyOutcome, ProxySource};
+use crate::requests::{
+    make_request, read_body, BodyError, RequestBody, RequestResult, SentRequest,
+};
 use crate::sherlock_target_manifest::{ErrorType, RequestMethod, TargetInfo};
+use crate::transport::SiteTransport;
+use crate::user_agent::UserAgentProfile;
 use crate::utils::Interpolatable;
 
+/// Bodies of probe steps are read up to this many bytes.
+const STEP_BODY_LIMIT: usize = 1024 * 1024;
+
+/// How long to wait before retrying a failed request.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
 #[derive(Error, Debug)]
 pub enum QueryError {
     #[error("Invalid username")]
     InvalidUsernameError,
     #[error("Request error")]
     RequestError,
+    /// The request timed out or no connection could be made, which may not
+    /// happen again when it is retried.
+    #[error("Connection error")]
+    ConnectionError,
     #[error(transparent)]
     RegexError(#[from] fancy_regex::Error),
+    #[error(transparent)]
+    MissingCredential(#[from] MissingCredential),
+    #[error(transparent)]
+    InvalidBody(#[from] BodyError),
+    #[error("Step {step} failed: {reason}")]
+    StepFailed { step: usize, reason: String },
+    #[error("{0}")]
+    ProxyUnavailable(String),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum QueryStatus {
     /// username detected
     Claimed,
@@ -34,7 +66,7 @@ pub enum QueryStatus {
     Waf,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct QueryResult {
     pub username: Arc<str>,
     pub site_name: Arc<str>,
@@ -44,6 +76,17 @@ pub struct QueryResult {
     pub http_status: Option<u16>,
     pub query_time: Duration,
     pub context: Option<String>,
+    pub metadata: Metadata,
+    /// How much the status can be trusted, from 0 to 1.
+    pub confidence: f32,
+}
+
+impl QueryResult {
+    /// A claimed result whose confidence is below the threshold.
+    #[must_use]
+    pub fn is_low_confidence(&self, min_confidence: f32) -> bool {
+        self.status == QueryStatus::Claimed && self.confidence < min_confidence
+    }
 }
 
 impl fmt::Display for QueryResult {
@@ -55,27 +98,71 @@ impl fmt::Display for QueryResult {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn add_result_to_channel(
     sender: Sender<RequestResult>,
     username: Arc<str>,
     site: Arc<str>,
     info: Arc<TargetInfo>,
     timeout: Duration,
-    proxy: Option<Arc<str>>,
+    proxy_source: ProxySource,
+    user_agent: Arc<UserAgentProfile>,
+    cookie_jar: Option<Arc<Jar>>,
+    credentials: SiteCredentials,
+    transport: SiteTransport,
+    cassette: Option<Arc<Cassette>>,
+    max_retries: u32,
 ) -> color_eyre::Result<()> {
     tokio::spawn(async move {
-        let encoded_username = &username.replace(' ', "%20");
-        let profile_url = info.url.interpolate(encoded_username);
-        let url_probe = match &info.url_probe {
-            // There is a special URL for probing existence separate
-            // from where the user profile normally can be found.
-            Some(url_probe) => url_probe.interpolate(encoded_username),
-            None => info.url.interpolate(encoded_username),
-        };
+        let profile_url = info.url.interpolate(&username.replace(' ', "%20"));
+        let url_probe = probe_url(&info, &username);
 
         let start = Instant::now();
-        let response =
-            check_user_at_site(&username, &url_probe, &info, timeout, proxy.as_deref()).await;
+        let mut retries = 0;
+        let mut proxy = None;
+        let outcome = loop {
+            // a pooled proxy is picked just before sending, so proxies removed meanwhile are avoided
+            proxy = match &proxy_source {
+                ProxySource::Fixed(proxy) => proxy.clone(),
+                ProxySource::Pool(pool) => match pool.pick(&site) {
+                    Some(proxy) => Some(proxy),
+                    None => {
+                        break Err(QueryError::ProxyUnavailable(String::from(
+                            "no healthy proxy left",
+                        )))
+                    }
+                },
+            };
+            let outcome = check_user_at_site(
+                &username,
+                &url_probe,
+                &info,
+                timeout,
+                proxy.as_deref(),
+                &user_agent,
+                cookie_jar.clone(),
+                &credentials,
+                &transport,
+                cassette.as_deref(),
+            )
+            .await;
+            match outcome {
+                Err(QueryError::ConnectionError) if retries < max_retries => {
+                    // the last attempt is recorded once its result is in
+                    if let (ProxySource::Pool(pool), Some(proxy)) = (&proxy_source, &proxy) {
+                        pool.record(proxy, ProxyOutcome::Failed);
+                    }
+                    retries += 1;
+                    debug!(site = %site, retries, "retrying failed request");
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+                outcome => break outcome,
+            }
+        };
+        let (request, response, secrets) = match outcome {
+            Ok((request, response, secrets)) => (Some(request), Ok(response), secrets),
+            Err(e) => (None, Err(e), Vec::new()),
+        };
         let duration = start.elapsed();
 
         let request_result = RequestResult {
@@ -84,8 +171,12 @@ pub fn add_result_to_channel(
             info,
             url: profile_url.clone(),
             url_probe,
+            request,
+            proxy,
             response,
+            secrets,
             query_time: duration,
+            retries,
         };
 
         // send to channel, ignore if the receiver has been dropped
@@ -95,17 +186,69 @@ pub fn add_result_to_channel(
     Ok(())
 }
 
+/// Report a site that couldn't be checked at all, e.g. because there is no
+/// proxy to send its request over.
+pub fn add_error_to_channel(
+    sender: Sender<RequestResult>,
+    username: Arc<str>,
+    site: Arc<str>,
+    info: Arc<TargetInfo>,
+    error: QueryError,
+) {
+    let request_result = RequestResult {
+        url: info.url.interpolate(&username.replace(' ', "%20")),
+        url_probe: probe_url(&info, &username),
+        username,
+        site,
+        info,
+        request: None,
+        proxy: None,
+        response: Err(error),
+        secrets: Vec::new(),
+        query_time: Duration::ZERO,
+        retries: 0,
+    };
+
+    tokio::spawn(async move {
+        // ignore if the receiver has been dropped
+        let _ = sender.send(request_result).await;
+    });
+}
+
+/// The URL requested to check `username` on a site.
+#[must_use]
+pub fn probe_url(info: &TargetInfo, username: &str) -> String {
+    let encoded_username = &username.replace(' ', "%20");
+    match &info.url_probe {
+        // There is a special URL for probing existence separate
+        // from where the user profile normally can be found.
+        Some(url_probe) => url_probe.interpolate(encoded_username),
+        None => info.url.interpolate(encoded_username),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn check_user_at_site(
     username: &str,
     url_probe: &str,
     info: &TargetInfo,
     timeout: Duration,
     proxy: Option<&str>,
-) -> Result<Response, QueryError> {
+    user_agent: &UserAgentProfile,
+    cookie_jar: Option<Arc<Jar>>,
+    credentials: &SiteCredentials,
+    transport: &SiteTransport,
+    cassette: Option<&Cassette>,
+) -> Result<(SentRequest, Response, Vec<String>), QueryError> {
     let request_body = info
         .request_payload
         .as_ref()
-        .map(|payload| payload.to_string().interpolate(username));
+        .map(|payload| payload.interpolate(username));
+
+    // credentials are filled in after the username, so it must not ask for one itself
+    if has_placeholder(username) {
+        return Err(QueryError::InvalidUsernameError);
+    }
 
     // use regex to make sure the url and username are valid for the site
     if let Some(regex) = &info.regex_check {
@@ -120,22 +263,158 @@ async fn check_user_at_site(
         // In most cases when we are detecting by status code,
         // it is not necessary to get the entire body:  we can
         // detect fine with just the HEAD response.
-        ErrorType::StatusCode { .. } => RequestMethod::Head,
+        ErrorType::StatusCode { .. } if !info.needs_body() => RequestMethod::Head,
         // Either this detect method needs the content associated
         // with the GET response, or this specific website will
         // not respond properly unless we request the whole page.
         _ => RequestMethod::Get,
     });
+
+    // credentials are only filled in now, so they stay out of results, logs and dumps
+    let mut secrets = Vec::new();
+
+    // tokens from earlier steps usually only work together with their session cookies
+    let cookie_jar = cookie_jar.or_else(|| info.steps.is_some().then(|| Arc::new(Jar::default())));
+
+    let mut tokens = HashMap::new();
+    for (index, step) in info.steps.iter().flatten().enumerate() {
+        let step_failed = |reason: String| QueryError::StepFailed {
+            step: index + 1,
+            reason,
+        };
+
+        let url = fill_tokens(
+            &credentials.resolve(&step.url.interpolate(username), &mut secrets)?,
+            &tokens,
+        );
+        let headers = resolve_headers(step.headers.as_ref(), credentials, &tokens, &mut secrets)?;
+        let body = step
+            .request_payload
+            .as_ref()
+            .map(|payload| {
+                let payload = fill_value_tokens(&payload.interpolate(username), &tokens);
+                RequestBody::new(step.request_body_type.unwrap_or_default(), payload)
+            })
+            .transpose()
+            .map_err(|e| step_failed(e.to_string()))?;
+
+        let (_, response) = make_request(
+            &url,
+            headers,
+            true,
+            timeout,
+            step.request_method.unwrap_or(RequestMethod::Get),
+            body,
+            proxy,
+            user_agent,
+            cookie_jar.clone(),
+            transport,
+            cassette,
+            &secrets,
+        )
+        .await
+        .map_err(|e| step_failed(e.to_string()))?;
+        let final_url = response.url().clone();
+        let body = read_body(response, STEP_BODY_LIMIT, |_| false)
+            .await
+            .map_err(|e| step_failed(e.to_string()))?;
+
+        let values = extract_metadata(&step.extract, &body.text, &final_url);
+        for name in step.extract.keys() {
+            let value = values
+                .get(name)
+                .and_then(|values| values.first())
+                .ok_or_else(|| step_failed(format!("no value for token '{name}'")))?;
+            // tokens end up in headers, which cannot hold newlines and other control characters
+            HeaderValue::from_str(value)
+                .map_err(|_| step_failed(format!("token '{name}' is not a valid header value")))?;
+            tokens.insert(name.clone(), value.clone());
+        }
+        debug!(
+            url = url_probe,
+            step = index + 1,
+            tokens = ?tokens.keys().collect::<Vec<_>>(),
+            "ran probe step"
+        );
+    }
+
+    let resolved_url = fill_tokens(&credentials.resolve(url_probe, &mut secrets)?, &tokens);
+    let headers = resolve_headers(info.headers.as_ref(), credentials, &tokens, &mut secrets)?;
+    let request_body = request_body
+        .map(|payload| {
+            let payload = fill_value_tokens(&payload, &tokens);
+            RequestBody::new(info.request_body_type.unwrap_or_default(), payload)
+        })
+        .transpose()?;
+
     make_request(
-        url_probe,
-        info.headers.clone(),
+        &resolved_url,
+        headers,
         allow_redirects,
         timeout,
         req_method,
         request_body,
         proxy,
-        None,
+        user_agent,
+        cookie_jar,
+        transport,
+        cassette,
+        &secrets,
     )
     .await
-    .map_err(|_| QueryError::RequestError)
+    .map(|(request, response)| (request, response, secrets))
+    .map_err(|e| {
+        debug!(url = url_probe, error = %e, "request failed");
+        match e.downcast_ref::<reqwest::Error>() {
+            Some(e) if e.is_timeout() || e.is_connect() => QueryError::ConnectionError,
+            _ => QueryError::RequestError,
+        }
+    })
+}
+
+/// Fill credentials and step tokens into a site's headers.
+fn resolve_headers(
+    headers: Option<&HashMap<String, String>>,
+    credentials: &SiteCredentials,
+    tokens: &HashMap<String, String>,
+    secrets: &mut Vec<String>,
+) -> Result<Option<HashMap<String, String>>, MissingCredential> {
+    headers
+        .map(|headers| {
+            headers
+                .iter()
+                .map(|(name, value)| {
+                    let value = credentials.resolve(value, secrets)?;
+                    Ok((name.clone(), fill_tokens(&value, tokens)))
+                })
+                .collect()
+        })
+        .transpose()
+}
+
+/// Replace the `{token.<name>}` placeholders in `text` with values from earlier steps.
+fn fill_tokens(text: &str, tokens: &HashMap<String, String>) -> String {
+    tokens.iter().fold(text.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{token.{name}}}"), value)
+    })
+}
+
+/// Like [`fill_tokens`], for every string inside a JSON value.
+fn fill_value_tokens(value: &Value, tokens: &HashMap<String, String>) -> Value {
+    match value {
+        Value::String(text) => Value::String(fill_tokens(text, tokens)),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| fill_value_tokens(item, tokens))
+                .collect(),
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, item)| (key.clone(), fill_value_tokens(item, tokens)))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
 }
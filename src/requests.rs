@@ -1,27 +1,39 @@
-use rand::seq::SliceRandom;
+use color_eyre::eyre::WrapErr;
+use serde_json::Value;
 use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use reqwest::{
-    header::{HeaderMap, HeaderName, HeaderValue},
+    cookie::Jar,
+    header::{HeaderMap, HeaderName, HeaderValue, COOKIE},
     redirect::Policy,
-    Client, Proxy, Response,
+    Client, Method, Proxy, Response, Url,
 };
 
+use thiserror::Error;
+use tracing::debug;
+
 use crate::{
+    cassette::{Cassette, CassetteMode},
     query::QueryError,
-    sherlock_target_manifest::{RequestMethod, TargetInfo},
+    sherlock_target_manifest::{BodyType, RequestMethod, TargetInfo},
+    transport::SiteTransport,
+    user_agent::UserAgentProfile,
 };
 
-const USER_AGENTS: [&str; 8] = [
-    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/114.0.0.0 Safari/537.36",
-    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:115.0) Gecko/20100101 Firefox/115.0",
-    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/114.0.0.0 Safari/537.36 Edg/114.0.1823.67",
-    "Mozilla/5.0 (Macintosh; Intel Mac OS X 12_0_1) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.1 Safari/605.1.15",
-    "Mozilla/5.0 (Macintosh; Intel Mac OS X 12_0_1) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/114.0.0.0 Safari/537.36",
-    "Mozilla/5.0 (Macintosh; Intel Mac OS X 12.0; rv:115.0) Gecko/20100101 Firefox/115.0",
-    "Mozilla/5.0 (X11; Ubuntu; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/114.0.0.0 Safari/537.36",
-    "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/116.0",
-];
+/// The stop condition of [`read_body`] is checked each time this many more bytes have arrived.
+const STOP_CHECK_BYTES: usize = 16 * 1024;
+
+/// How much of the text before the new bytes the stop condition sees again,
+/// so text arriving across two checks is still found.
+const STOP_OVERLAP_BYTES: usize = 4 * 1024;
+
+/// The request line and headers that were actually sent for a probe.
+#[derive(Debug, Clone)]
+pub struct SentRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: HeaderMap,
+}
 
 #[derive(Debug)]
 pub struct RequestResult {
@@ -30,54 +42,188 @@ pub struct RequestResult {
     pub info: Arc<TargetInfo>,
     pub url: String,
     pub url_probe: String,
+    pub request: Option<SentRequest>,
+    /// The proxy the request was made over.
+    pub proxy: Option<Arc<str>>,
     pub response: Result<Response, QueryError>,
+    /// Credentials filled into the request, to hide wherever its URLs are shown.
+    pub secrets: Vec<String>,
     pub query_time: Duration,
+    /// How many times the request was retried after failing.
+    pub retries: u32,
+}
+
+#[derive(Error, Debug)]
+pub enum BodyError {
+    #[error("a form body must be a JSON object")]
+    FormNotObject,
+    #[error("a raw body must be a string")]
+    RawNotString,
+    #[error("a GraphQL body must be a query string or an object with a `query`")]
+    GraphqlWithoutQuery,
+}
+
+/// A request body, encoded as the site's `request_body_type` says.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestBody {
+    Json(Value),
+    Form(Vec<(String, String)>),
+    Raw(String),
+}
+
+impl RequestBody {
+    pub fn new(body_type: BodyType, payload: Value) -> Result<Self, BodyError> {
+        match (body_type, payload) {
+            (BodyType::Json, payload) => Ok(Self::Json(payload)),
+            (BodyType::Form, Value::Object(fields)) => Ok(Self::Form(
+                fields
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let value = match value {
+                            Value::String(text) => text,
+                            Value::Null => String::new(),
+                            value => value.to_string(),
+                        };
+                        (name, value)
+                    })
+                    .collect(),
+            )),
+            (BodyType::Form, _) => Err(BodyError::FormNotObject),
+            (BodyType::Raw, Value::String(text)) => Ok(Self::Raw(text)),
+            (BodyType::Raw, _) => Err(BodyError::RawNotString),
+            (BodyType::Graphql, Value::String(query)) => {
+                Ok(Self::Json(serde_json::json!({ "query": query })))
+            }
+            (BodyType::Graphql, Value::Object(fields)) if fields.contains_key("query") => {
+                Ok(Self::Json(Value::Object(fields)))
+            }
+            (BodyType::Graphql, _) => Err(BodyError::GraphqlWithoutQuery),
+        }
+    }
 }
 
+/// A response body read by [`read_body`].
+#[derive(Debug, Default)]
+pub struct ResponseBody {
+    pub text: String,
+    /// Whether the body was cut off at the size limit.
+    pub truncated: bool,
+    /// Whether reading stopped early because the stop condition held.
+    pub stopped: bool,
+}
+
+/// Stream a response body, stopping early once `max_size` bytes have been
+/// read or `stop` returns true.
+///
+/// `stop` only sees the text that arrived since it was last called, plus
+/// [`STOP_OVERLAP_BYTES`] before it, so large bodies aren't scanned over and over.
+pub async fn read_body(
+    mut response: Response,
+    max_size: usize,
+    stop: impl Fn(&str) -> bool,
+) -> reqwest::Result<ResponseBody> {
+    let mut bytes = Vec::new();
+    let mut checked = 0;
+    let mut truncated = false;
+    let mut stopped = false;
+
+    while let Some(chunk) = response.chunk().await? {
+        let take = chunk.len().min(max_size - bytes.len());
+        bytes.extend_from_slice(&chunk[..take]);
+        if take < chunk.len() {
+            truncated = true;
+            break;
+        }
+
+        if bytes.len() - checked >= STOP_CHECK_BYTES {
+            let window = &bytes[checked.saturating_sub(STOP_OVERLAP_BYTES)..];
+            checked = bytes.len();
+            if stop(&String::from_utf8_lossy(window)) {
+                stopped = true;
+                break;
+            }
+        }
+    }
+
+    Ok(ResponseBody {
+        text: String::from_utf8_lossy(&bytes).into_owned(),
+        truncated,
+        stopped,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn make_request(
     url: &str,
     headers: Option<HashMap<String, String>>,
     allow_redirects: bool,
     timeout: Duration,
     method: RequestMethod,
-    request_payload: Option<String>,
+    body: Option<RequestBody>,
     proxy: Option<&str>,
-    user_agent: Option<String>,
-) -> color_eyre::Result<Response> {
+    user_agent: &UserAgentProfile,
+    cookie_jar: Option<Arc<Jar>>,
+    transport: &SiteTransport,
+    cassette: Option<&Cassette>,
+    secrets: &[String],
+) -> color_eyre::Result<(SentRequest, Response)> {
+    let redirect_secrets = secrets.to_vec();
     let redirect_policy = match allow_redirects {
-        true => Policy::limited(5),
+        true => Policy::custom(move |attempt| {
+            if attempt.previous().len() >= 5 {
+                return attempt.error("too many redirects");
+            }
+
+            if let Some(from) = attempt.previous().last() {
+                debug!(
+                    from = redact(from.as_str(), &redirect_secrets),
+                    to = redact(attempt.url().as_str(), &redirect_secrets),
+                    status = attempt.status().as_u16(),
+                    "following redirect"
+                );
+            }
+            attempt.follow()
+        }),
         false => Policy::none(),
     };
 
-    let headers_map = headers
+    let mut headers_map = headers
         .unwrap_or_default()
         .into_iter()
         .map(|(key, value)| {
-            let header_name = key.parse::<HeaderName>().unwrap();
-            let header_value = value.parse::<HeaderValue>().unwrap();
-            (header_name, header_value)
+            let header_name = key.parse::<HeaderName>()?;
+            // the value is left out of the error as it may hold a credential
+            let header_value = value
+                .parse::<HeaderValue>()
+                .wrap_err_with(|| format!("Invalid value for header '{header_name}'"))?;
+            Ok((header_name, header_value))
         })
-        .collect::<HeaderMap>();
+        .collect::<color_eyre::Result<HeaderMap>>()?;
 
     let req_method = match method {
-        RequestMethod::Get => reqwest::Method::GET,
-        RequestMethod::Post => reqwest::Method::POST,
-        RequestMethod::Put => reqwest::Method::PUT,
-        RequestMethod::Head => reqwest::Method::HEAD,
+        RequestMethod::Get => Method::GET,
+        RequestMethod::Post => Method::POST,
+        RequestMethod::Put => Method::PUT,
+        RequestMethod::Head => Method::HEAD,
+        RequestMethod::Patch => Method::PATCH,
+        RequestMethod::Options => Method::OPTIONS,
     };
 
-    let random_agent = USER_AGENTS
-        .choose(&mut rand::thread_rng())
-        .unwrap()
-        .to_owned();
-
-    let req_user_agent = user_agent.unwrap_or(random_agent.into());
+    let mut builder = transport.configure(
+        Client::builder().timeout(timeout).redirect(redirect_policy),
+        proxy.is_some(),
+    )?;
 
-    let mut builder = Client::builder()
-        .default_headers(headers_map)
-        .user_agent(req_user_agent)
-        .timeout(timeout)
-        .redirect(redirect_policy);
+    if let Some(jar) = cookie_jar {
+        // the site's cookies join the jar so they are kept up to date across redirects
+        if let Some(cookie) = headers_map.remove(COOKIE) {
+            let url = Url::parse(url)?;
+            for pair in cookie.to_str()?.split(';').map(str::trim) {
+                jar.add_cookie_str(pair, &url);
+            }
+        }
+        builder = builder.cookie_provider(jar);
+    }
 
     if let Some(proxy) = proxy {
         builder = builder.proxy(Proxy::all(proxy)?);
@@ -85,11 +231,209 @@ pub async fn make_request(
 
     let client = builder.build()?;
 
-    let resp = client
+    let request = client
         .request(req_method, url)
-        .json(&request_payload)
-        .send()
-        .await?;
+        .headers(user_agent.headers.clone());
+    let request = match transport.request_version() {
+        Some(version) => request.version(version),
+        None => request,
+    };
+    let request = match body {
+        Some(RequestBody::Json(payload)) => request.json(&payload),
+        Some(RequestBody::Form(fields)) => request.form(&fields),
+        Some(RequestBody::Raw(text)) => request.body(text),
+        None => request,
+    };
+    let request = request
+        // the site's own headers take precedence over the profile's and the body's content type
+        .headers(headers_map)
+        .build()
+        .map_err(|e| hide_url(e, secrets))?;
+
+    // never let credentials reach logs, dumps or cassettes
+    let sent = SentRequest {
+        method: request.method().clone(),
+        url: redact(request.url().as_str(), secrets),
+        headers: redact_headers(request.headers(), secrets),
+    };
+    let url = sent.url.as_str();
+
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned());
 
-    Ok(resp)
+    let resp = match cassette {
+        Some(cassette) if cassette.mode() == CassetteMode::Replay => {
+            debug!(method = %sent.method, url, "replaying request");
+            cassette.play(&sent, body.as_deref())?
+        }
+        Some(cassette) => {
+            debug!(method = %sent.method, url, "sending request");
+            let resp = client
+                .execute(request)
+                .await
+                .map_err(|e| hide_url(e, secrets))?;
+            cassette
+                .capture(&sent, body.as_deref(), resp, secrets)
+                .await?
+        }
+        None => {
+            debug!(method = %sent.method, url, "sending request");
+            client
+                .execute(request)
+                .await
+                .map_err(|e| hide_url(e, secrets))?
+        }
+    };
+
+    Ok((sent, resp))
+}
+
+/// Replace every secret in `text` with a marker.
+pub(crate) fn redact(text: &str, secrets: &[String]) -> String {
+    secrets
+        .iter()
+        .filter(|secret| !secret.is_empty())
+        .fold(text.to_string(), |text, secret| {
+            text.replace(secret.as_str(), "[REDACTED]")
+        })
+}
+
+pub(crate) fn redact_headers(headers: &HeaderMap, secrets: &[String]) -> HeaderMap {
+    let mut redacted = headers.clone();
+    for value in redacted.values_mut() {
+        let Ok(text) = value.to_str() else {
+            continue;
+        };
+        if secrets
+            .iter()
+            .any(|secret| !secret.is_empty() && text.contains(secret.as_str()))
+        {
+            if let Ok(mut hidden) = HeaderValue::from_str(&redact(text, secrets)) {
+                hidden.set_sensitive(true);
+                *value = hidden;
+            }
+        }
+    }
+
+    redacted
+}
+
+/// Errors carry the request URL, which may hold credentials.
+fn hide_url(error: reqwest::Error, secrets: &[String]) -> reqwest::Error {
+    match secrets.is_empty() {
+        true => error,
+        false => error.without_url(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn response(body: &str) -> Response {
+        Response::from(http::Response::new(body.to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_read_body() {
+        let body = read_body(response("hello"), 1024, |_| false).await.unwrap();
+        assert_eq!(body.text, "hello");
+        assert!(!body.truncated);
+
+        let body = read_body(response("hello"), 5, |_| false).await.unwrap();
+        assert_eq!(body.text, "hello");
+        assert!(!body.truncated);
+
+        let body = read_body(response("hello world"), 5, |_| false)
+            .await
+            .unwrap();
+        assert_eq!(body.text, "hello");
+        assert!(body.truncated);
+
+        let large = "x".repeat(STOP_CHECK_BYTES);
+        let body = read_body(response(&large), usize::MAX, |text| text.contains('x'))
+            .await
+            .unwrap();
+        assert!(body.stopped);
+        assert!(!body.truncated);
+    }
+
+    #[tokio::test]
+    async fn test_read_body_checks_new_text_only() {
+        use tokio::io::AsyncWriteExt;
+
+        // the marker is split across the second and third chunks
+        let mut chunks = vec![" ".repeat(STOP_CHECK_BYTES); 4];
+        chunks[1].replace_range(STOP_CHECK_BYTES - 3.., "MAR");
+        chunks[2].replace_range(..3, "KER");
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let head = "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n";
+            stream.write_all(head.as_bytes()).await.unwrap();
+            for chunk in chunks {
+                let frame = format!("{:x}\r\n{chunk}\r\n", chunk.len());
+                stream.write_all(frame.as_bytes()).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            let _ = stream.write_all(b"0\r\n\r\n").await;
+        });
+
+        let response = reqwest::get(format!("http://{address}")).await.unwrap();
+        let longest = std::sync::Mutex::new(0);
+        let body = read_body(response, usize::MAX, |text| {
+            let mut longest = longest.lock().unwrap();
+            *longest = text.len().max(*longest);
+            text.contains("MARKER")
+        })
+        .await
+        .unwrap();
+
+        assert!(body.stopped);
+        assert_eq!(body.text.len(), 3 * STOP_CHECK_BYTES);
+        assert!(*longest.lock().unwrap() <= STOP_CHECK_BYTES + STOP_OVERLAP_BYTES);
+    }
+
+    #[test]
+    fn test_request_body() {
+        assert_eq!(
+            RequestBody::new(BodyType::Form, json!({ "user": "alice", "page": 1 })).unwrap(),
+            RequestBody::Form(vec![
+                ("page".to_string(), "1".to_string()),
+                ("user".to_string(), "alice".to_string()),
+            ])
+        );
+        assert!(RequestBody::new(BodyType::Form, json!("user=alice")).is_err());
+        assert_eq!(
+            RequestBody::new(BodyType::Raw, json!("user=alice")).unwrap(),
+            RequestBody::Raw("user=alice".to_string())
+        );
+        assert!(RequestBody::new(BodyType::Raw, json!({})).is_err());
+        assert_eq!(
+            RequestBody::new(BodyType::Graphql, json!("{ user { id } }")).unwrap(),
+            RequestBody::Json(json!({ "query": "{ user { id } }" }))
+        );
+        assert!(RequestBody::new(BodyType::Graphql, json!({ "variables": {} })).is_err());
+    }
+
+    #[test]
+    fn test_redact() {
+        let secrets = ["token".to_string(), String::new()];
+        assert_eq!(
+            redact("https://api.example.com/?key=token", &secrets),
+            "https://api.example.com/?key=[REDACTED]"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer token"));
+        headers.insert("accept", HeaderValue::from_static("*/*"));
+        let redacted = redact_headers(&headers, &secrets);
+        assert_eq!(redacted["authorization"], "Bearer [REDACTED]");
+        assert_eq!(redacted["accept"], "*/*");
+    }
 }
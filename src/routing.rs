@@ -0,0 +1,207 @@
+use crate::proxy_pool::validate_proxy;
+use crate::query::probe_url;
+use crate::sherlock_target_manifest::TargetInfo;
+use color_eyre::eyre::{self, WrapErr};
+use regex::Regex;
+use reqwest::Url;
+use serde::Deserialize;
+
+/// Where the requests for a site go.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Route {
+    /// Connect directly, ignoring `--proxy` and `--proxy-file`. Can't be used with `--tor`.
+    Direct,
+    /// Don't check the site at all.
+    Skip,
+    /// Connect over this proxy URL. Can't be used with `--tor`.
+    Proxy(String),
+}
+
+/// A host name pattern where `*` matches any run of characters, e.g. `*.example.com`.
+#[derive(Debug, Clone)]
+pub struct HostPattern(Regex);
+
+impl TryFrom<String> for HostPattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        let regex = regex::escape(&pattern).replace(r"\*", ".*");
+        Regex::new(&format!("(?i)^{regex}$")).map(Self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HostPattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Self::try_from(pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+impl HostPattern {
+    #[must_use]
+    pub fn is_match(&self, host: &str) -> bool {
+        self.0.is_match(host)
+    }
+}
+
+/// A routing rule. It applies to a site when every selector it sets matches
+/// the site, so a rule without selectors applies to every site.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteRule {
+    /// Site names, as in the manifest.
+    #[serde(default)]
+    pub sites: Vec<String>,
+    /// Manifest tags, ignoring case.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Patterns for the host of the probed URL.
+    #[serde(default)]
+    pub hosts: Vec<HostPattern>,
+    pub route: Route,
+}
+
+impl RouteRule {
+    #[must_use]
+    pub fn matches(&self, site: &str, info: &TargetInfo, host: Option<&str>) -> bool {
+        let site_matches = self.sites.is_empty() || self.sites.iter().any(|s| s == site);
+        let tag_matches = self.tags.is_empty()
+            || info
+                .tags
+                .as_ref()
+                .is_some_and(|tags| self.tags.iter().any(|tag| tags.contains(tag)));
+        let host_matches = self.hosts.is_empty()
+            || host.is_some_and(|host| self.hosts.iter().any(|pattern| pattern.is_match(host)));
+
+        site_matches && tag_matches && host_matches
+    }
+}
+
+/// Per-site routing rules, checked in order until one matches.
+#[derive(Debug, Clone, Default)]
+pub struct Routing {
+    rules: Vec<RouteRule>,
+}
+
+impl Routing {
+    pub fn new(rules: Vec<RouteRule>) -> color_eyre::Result<Self> {
+        for rule in &rules {
+            if let Route::Proxy(proxy) = &rule.route {
+                validate_proxy(proxy)?;
+            }
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// Load routing rules from a JSON file holding a list of rules.
+    pub fn load(path: &str) -> color_eyre::Result<Self> {
+        let json = std::fs::read_to_string(path).wrap_err_with(|| {
+            format!("Problem while attempting to access routing file '{path}'")
+        })?;
+        let deserializer = &mut serde_json::Deserializer::from_str(&json);
+        let rules = serde_path_to_error::deserialize(deserializer)
+            .map_err(|err| eyre::eyre!("{} at [{}]", err.inner(), err.path()))
+            .and_then(Self::new)
+            .wrap_err_with(|| format!("Problem parsing routing file '{path}'"))?;
+
+        Ok(rules)
+    }
+
+    /// Make sure no rule sends requests around Tor, which only allows `skip` rules.
+    pub fn require_tor(&self) -> color_eyre::Result<()> {
+        match self.rules.iter().position(|rule| rule.route != Route::Skip) {
+            Some(index) => Err(eyre::eyre!(
+                "Routing rule {} would bypass Tor: only skip rules can be used with --tor",
+                index + 1
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The route for checking `username` on `site`, if any rule applies.
+    #[must_use]
+    pub fn route(&self, site: &str, info: &TargetInfo, username: &str) -> Option<&Route> {
+        let url = Url::parse(&probe_url(info, username)).ok();
+        let host = url.as_ref().and_then(Url::host_str);
+
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(site, info, host))
+            .map(|rule| &rule.route)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn target(url: &str, tags: serde_json::Value) -> TargetInfo {
+        serde_json::from_value(json!({
+            "url": url,
+            "urlMain": url,
+            "username_claimed": "alice",
+            "errorType": "status_code",
+            "tags": tags,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_route() {
+        let rules = serde_json::from_value(json!([
+            { "sites": ["GitHub"], "route": "direct" },
+            { "hosts": ["*.corp.example"], "route": "skip" },
+            { "tags": ["RU"], "route": { "proxy": "socks5://127.0.0.1:1080" } },
+        ]))
+        .unwrap();
+        let routing = Routing::new(rules).unwrap();
+
+        let github = target("https://github.com/{}", json!("coding"));
+        assert_eq!(
+            routing.route("GitHub", &github, "alice"),
+            Some(&Route::Direct)
+        );
+        assert_eq!(routing.route("GitLab", &github, "alice"), None);
+
+        let wiki = target("https://{}.wiki.corp.example/", json!(null));
+        assert_eq!(routing.route("Wiki", &wiki, "alice"), Some(&Route::Skip));
+
+        let vk = target("https://vk.com/{}", json!(["social", "ru"]));
+        assert_eq!(
+            routing.route("VK", &vk, "alice"),
+            Some(&Route::Proxy("socks5://127.0.0.1:1080".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let rules =
+            serde_json::from_value(json!([{ "route": { "proxy": "ftp://host" } }])).unwrap();
+        assert!(Routing::new(rules).is_err());
+        assert!(serde_json::from_value::<RouteRule>(json!({ "route": "sideways" })).is_err());
+    }
+
+    #[test]
+    fn test_require_tor() {
+        let skip_only =
+            serde_json::from_value(json!([{ "tags": ["RU"], "route": "skip" }])).unwrap();
+        assert!(Routing::new(skip_only).unwrap().require_tor().is_ok());
+
+        let direct = serde_json::from_value(json!([
+            { "tags": ["RU"], "route": "skip" },
+            { "sites": ["GitHub"], "route": "direct" },
+        ]))
+        .unwrap();
+        let error = Routing::new(direct).unwrap().require_tor().unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Routing rule 2 would bypass Tor"));
+
+        let proxy =
+            serde_json::from_value(json!([{ "route": { "proxy": "http://127.0.0.1:8080" } }]))
+                .unwrap();
+        assert!(Routing::new(proxy).unwrap().require_tor().is_err());
+    }
+}
@@ -1,5 +1,8 @@
+use crate::extract::Extractor;
+use crate::json_path::JsonCondition;
 use core::fmt;
-use serde::{Deserialize, Serialize};
+use regex::Regex;
+use serde::{Deserialize, Serialize, Serializer};
 use serde_json::Value;
 use std::collections::HashMap;
 
@@ -33,11 +36,30 @@ pub struct TargetInfo {
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub request_payload: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub request_body_type: Option<BodyType>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
     pub __comment__: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub tags: Option<Tags>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub request_method: Option<RequestMethod>,
+    /// Text that must appear in the response for the username to count as claimed.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        rename = "claimedMsg",
+        default
+    )]
+    pub claimed_msg: Option<ErrorMsg>,
+    /// Evidence that must all be present for the username to count as claimed.
+    /// Without it any response that isn't detected as absent is claimed.
+    #[serde(skip_serializing_if = "Option::is_none", rename = "claimedIf", default)]
+    pub claimed_if: Option<Evidence>,
+    /// Rules for pulling profile metadata out of a claimed page, keyed by field name.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub extract: Option<HashMap<String, Extractor>>,
+    /// Requests made before the probe, e.g. to obtain a CSRF or guest token.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub steps: Option<Vec<ProbeStep>>,
 
     #[serde(flatten)]
     pub error_type: ErrorType,
@@ -45,6 +67,43 @@ pub struct TargetInfo {
     // in any of the targets in the official repository
 }
 
+impl TargetInfo {
+    /// Whether detection for this site looks at the response body.
+    #[must_use]
+    pub fn needs_body(&self) -> bool {
+        let detection_needs_body = match &self.error_type {
+            ErrorType::Message { .. } | ErrorType::JsonPath { .. } => true,
+            ErrorType::Rules { absent } => absent.needs_body(),
+            ErrorType::StatusCode { .. } | ErrorType::ResponseUrl { .. } => false,
+        };
+
+        detection_needs_body
+            || self.claimed_msg.is_some()
+            || self.extract.is_some()
+            || self.claimed_if.as_ref().is_some_and(Evidence::needs_body)
+    }
+}
+
+/// A request made before the probe to obtain values the probe needs.
+///
+/// Each value extracted from the response can be used in the `url`,
+/// `headers` and `request_payload` of later steps and of the probe as
+/// `{token.<name>}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProbeStep {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub request_method: Option<RequestMethod>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub request_payload: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub request_body_type: Option<BodyType>,
+    /// The values to pull out of the response, keyed by token name.
+    pub extract: HashMap<String, Extractor>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "errorType", rename_all = "snake_case")]
 pub enum ErrorType {
@@ -61,6 +120,77 @@ pub enum ErrorType {
         #[serde(skip_serializing_if = "Option::is_none", default)]
         codes: Option<ErrorCode>,
     },
+    /// The response is JSON and the username is available when `errorPath` holds.
+    JsonPath {
+        #[serde(rename = "errorPath")]
+        condition: JsonCondition,
+    },
+    /// The username is available when every condition in `absentIf` holds.
+    Rules {
+        #[serde(rename = "absentIf")]
+        absent: Evidence,
+    },
+}
+
+/// A set of conditions on a response that must all hold for the evidence to match.
+/// Evidence without any conditions never matches.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Evidence {
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        rename = "statusCode",
+        default
+    )]
+    pub status_code: Option<ErrorCode>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub message: Option<ErrorMsg>,
+    /// The username appears in the response body.
+    #[serde(
+        skip_serializing_if = "std::ops::Not::not",
+        rename = "usernameInBody",
+        default
+    )]
+    pub username_in_body: bool,
+    /// The response body is JSON and this field exists and isn't `null`,
+    /// or compares as written, e.g. `$.user.type == "person"`.
+    #[serde(skip_serializing_if = "Option::is_none", rename = "jsonField", default)]
+    pub json_field: Option<JsonCondition>,
+}
+
+impl Evidence {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.status_code.is_none()
+            && self.message.is_none()
+            && !self.username_in_body
+            && self.json_field.is_none()
+    }
+
+    /// Whether any condition looks at the response body.
+    #[must_use]
+    pub fn needs_body(&self) -> bool {
+        self.message.is_some() || self.username_in_body || self.json_field.is_some()
+    }
+
+    /// Whether every condition holds for a response.
+    #[must_use]
+    pub fn matches(&self, status_code: u16, body: &str, username: &str) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
+        let status_matches = self
+            .status_code
+            .as_ref()
+            .is_none_or(|codes| codes.contains(&status_code));
+        let message_matches = self.message.as_ref().is_none_or(|msg| msg.is_in(body));
+        let username_matches = !self.username_in_body || body.contains(username);
+        let json_matches = self.json_field.as_ref().is_none_or(|condition| {
+            serde_json::from_str::<Value>(body).is_ok_and(|document| condition.evaluate(&document))
+        });
+
+        status_matches && message_matches && username_matches && json_matches
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -70,6 +200,17 @@ pub enum Tags {
     Multiple(Vec<String>),
 }
 
+impl Tags {
+    /// Whether `tag` is one of the tags, ignoring case.
+    #[must_use]
+    pub fn contains(&self, tag: &str) -> bool {
+        match self {
+            Tags::Single(single) => single.eq_ignore_ascii_case(tag),
+            Tags::Multiple(multiple) => multiple.iter().any(|t| t.eq_ignore_ascii_case(tag)),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum RequestMethod {
@@ -77,21 +218,39 @@ pub enum RequestMethod {
     Post,
     Head,
     Put,
+    Patch,
+    Options,
+}
+
+/// How `request_payload` is encoded into the request body.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyType {
+    /// The payload as JSON.
+    #[default]
+    Json,
+    /// A JSON object of fields, sent as `application/x-www-form-urlencoded`.
+    Form,
+    /// A string, sent as is.
+    Raw,
+    /// A query string, or an object with `query` and optionally `variables`
+    /// and `operationName`, sent as a GraphQL JSON request.
+    Graphql,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
-#[serde(untagged)]
+#[serde(untagged, try_from = "RawErrorMsg")]
 pub enum ErrorMsg {
-    Single(String),
-    Multiple(Vec<String>),
+    Single(MessagePattern),
+    Multiple(Vec<MessagePattern>),
 }
 
 impl ErrorMsg {
     #[must_use]
     pub fn is_in(&self, text: &str) -> bool {
         match self {
-            ErrorMsg::Single(msg) => text.contains(msg),
-            ErrorMsg::Multiple(msgs) => msgs.iter().any(|msg| text.contains(msg)),
+            ErrorMsg::Single(msg) => msg.is_in(text),
+            ErrorMsg::Multiple(msgs) => msgs.iter().any(|msg| msg.is_in(text)),
         }
     }
 }
@@ -99,8 +258,90 @@ impl ErrorMsg {
 impl fmt::Debug for ErrorMsg {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ErrorMsg::Single(c) => write!(f, "{c}"),
-            ErrorMsg::Multiple(codes) => codes.iter().fold(Ok(()), |_, c| write!(f, "{c}, ")),
+            ErrorMsg::Single(c) => write!(f, "{c:?}"),
+            ErrorMsg::Multiple(codes) => codes.iter().try_for_each(|c| write!(f, "{c:?}, ")),
+        }
+    }
+}
+
+/// A message to look for in a response body, either as plain text
+/// or as a regular expression written as `{"regex": "..."}`.
+#[derive(Serialize, Clone)]
+#[serde(untagged)]
+pub enum MessagePattern {
+    Text(String),
+    Regex { regex: MessageRegex },
+}
+
+impl MessagePattern {
+    #[must_use]
+    pub fn is_in(&self, text: &str) -> bool {
+        match self {
+            MessagePattern::Text(msg) => text.contains(msg),
+            MessagePattern::Regex { regex } => regex.0.is_match(text),
+        }
+    }
+}
+
+impl fmt::Debug for MessagePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessagePattern::Text(msg) => write!(f, "{msg}"),
+            MessagePattern::Regex { regex } => write!(f, "/{}/", regex.0.as_str()),
+        }
+    }
+}
+
+/// A regular expression that is compiled when the manifest is loaded,
+/// so an invalid pattern is reported up front instead of once per request.
+#[derive(Debug, Clone)]
+pub struct MessageRegex(pub Regex);
+
+impl Serialize for MessageRegex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+/// The message patterns as written in the manifest, before regexes are compiled.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawErrorMsg {
+    Single(RawMessagePattern),
+    Multiple(Vec<RawMessagePattern>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawMessagePattern {
+    Text(String),
+    Regex { regex: String },
+}
+
+impl TryFrom<RawMessagePattern> for MessagePattern {
+    type Error = regex::Error;
+
+    fn try_from(raw: RawMessagePattern) -> Result<Self, Self::Error> {
+        match raw {
+            RawMessagePattern::Text(msg) => Ok(MessagePattern::Text(msg)),
+            RawMessagePattern::Regex { regex } => Ok(MessagePattern::Regex {
+                regex: MessageRegex(Regex::new(&regex)?),
+            }),
+        }
+    }
+}
+
+impl TryFrom<RawErrorMsg> for ErrorMsg {
+    type Error = regex::Error;
+
+    fn try_from(raw: RawErrorMsg) -> Result<Self, Self::Error> {
+        match raw {
+            RawErrorMsg::Single(msg) => Ok(ErrorMsg::Single(msg.try_into()?)),
+            RawErrorMsg::Multiple(msgs) => Ok(ErrorMsg::Multiple(
+                msgs.into_iter()
+                    .map(MessagePattern::try_from)
+                    .collect::<Result<_, _>>()?,
+            )),
         }
     }
 }
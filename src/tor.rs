@@ -0,0 +1,204 @@
+use color_eyre::eyre::{self, WrapErr};
+use reqwest::Url;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+
+/// Tor ignores NEWNYM signals sent more often than this.
+const NEWNYM_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct TorOptions {
+    /// Address of the Tor SOCKS port, e.g. `127.0.0.1:9050`.
+    pub socks: String,
+    /// Address of the Tor control port, needed to request new circuits.
+    pub control: Option<String>,
+    pub control_password: Option<String>,
+    /// Use a separate circuit for each site.
+    pub isolate: bool,
+}
+
+/// Routes requests over a local Tor daemon.
+#[derive(Debug)]
+pub struct Tor {
+    options: TorOptions,
+    /// Bumped on every new circuit so isolated streams don't reuse old ones.
+    generation: AtomicU64,
+    last_newnym: Mutex<Option<Instant>>,
+}
+
+impl Tor {
+    #[must_use]
+    pub fn new(options: TorOptions) -> Self {
+        Self {
+            options,
+            generation: AtomicU64::new(0),
+            last_newnym: Mutex::new(None),
+        }
+    }
+
+    /// Make sure the SOCKS port is reachable, so a scan never starts without Tor.
+    pub async fn check(&self) -> color_eyre::Result<()> {
+        TcpStream::connect(&self.options.socks)
+            .await
+            .wrap_err_with(|| format!("Tor SOCKS port {} is not reachable", self.options.socks))?;
+
+        Ok(())
+    }
+
+    /// The proxy URL to use for requests to `site`.
+    ///
+    /// Names resolve on the Tor side (`socks5h`). With isolation, each site
+    /// gets its own SOCKS credentials, which Tor puts on separate circuits.
+    pub fn proxy_url(&self, site: &str) -> color_eyre::Result<String> {
+        let mut url = Url::parse(&format!("socks5h://{}", self.options.socks))
+            .wrap_err_with(|| format!("Invalid Tor SOCKS address '{}'", self.options.socks))?;
+
+        if self.options.isolate {
+            let generation = self.generation.load(Ordering::Relaxed).to_string();
+            url.set_username(site)
+                .and_then(|()| url.set_password(Some(&generation)))
+                .map_err(|()| eyre::eyre!("Invalid Tor SOCKS address '{}'", self.options.socks))?;
+        }
+
+        Ok(url.to_string())
+    }
+
+    /// Ask Tor for new circuits after a block or rate limit.
+    ///
+    /// Does nothing without a control port or if a new circuit was requested
+    /// less than ten seconds ago. Returns whether a new circuit was requested.
+    pub async fn new_circuit(&self) -> color_eyre::Result<bool> {
+        let Some(control) = &self.options.control else {
+            return Ok(false);
+        };
+
+        {
+            let mut last_newnym = self.last_newnym.lock().unwrap();
+            if last_newnym.is_some_and(|last| last.elapsed() < NEWNYM_INTERVAL) {
+                return Ok(false);
+            }
+            *last_newnym = Some(Instant::now());
+        }
+
+        let stream = TcpStream::connect(control)
+            .await
+            .wrap_err_with(|| format!("Tor control port {control} is not reachable"))?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        let authenticate = match &self.options.control_password {
+            Some(password) => format!(
+                "AUTHENTICATE \"{}\"\r\n",
+                password.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+            None => "AUTHENTICATE\r\n".to_string(),
+        };
+        for command in [authenticate.as_str(), "SIGNAL NEWNYM\r\n"] {
+            writer.write_all(command.as_bytes()).await?;
+            let reply = read_reply(&mut reader).await?;
+            if !reply.starts_with("250") {
+                let command = command.split_whitespace().next().unwrap_or_default();
+                return Err(eyre::eyre!("Tor control port refused {command}: {reply}"));
+            }
+        }
+        let _ = writer.write_all(b"QUIT\r\n").await;
+
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        debug!(control, "requested new Tor circuits");
+
+        Ok(true)
+    }
+
+    /// Request new circuits, logging instead of failing.
+    pub async fn rotate(&self, site: &str) {
+        match self.new_circuit().await {
+            Ok(true) => debug!(site, "rotated Tor circuits after block"),
+            Ok(false) => {}
+            Err(e) => warn!(site, error = %e, "failed to rotate Tor circuits"),
+        }
+    }
+}
+
+/// Read a control port reply, returning its final line.
+async fn read_reply<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> color_eyre::Result<String> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(eyre::eyre!("Tor control port closed the connection"));
+        }
+        // intermediate lines of a multi-line reply have `-` or `+` after the code
+        if line.as_bytes().get(3) == Some(&b' ') {
+            return Ok(line.trim_end().to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn options() -> TorOptions {
+        TorOptions {
+            socks: "127.0.0.1:9050".to_string(),
+            control: None,
+            control_password: None,
+            isolate: false,
+        }
+    }
+
+    #[test]
+    fn test_proxy_url() {
+        let tor = Tor::new(options());
+        assert_eq!(tor.proxy_url("GitHub").unwrap(), "socks5h://127.0.0.1:9050");
+
+        let tor = Tor::new(TorOptions {
+            isolate: true,
+            ..options()
+        });
+        assert_eq!(
+            tor.proxy_url("Ask Fedora").unwrap(),
+            "socks5h://Ask%20Fedora:0@127.0.0.1:9050"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_new_circuit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let control = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut commands = Vec::new();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                if line == "QUIT" {
+                    break;
+                }
+                commands.push(line);
+                writer.write_all(b"250 OK\r\n").await.unwrap();
+            }
+            commands
+        });
+
+        let tor = Tor::new(TorOptions {
+            control: Some(control),
+            control_password: Some("pa\"ss".to_string()),
+            isolate: true,
+            ..options()
+        });
+        assert!(tor.new_circuit().await.unwrap());
+        assert_eq!(
+            server.await.unwrap(),
+            ["AUTHENTICATE \"pa\\\"ss\"", "SIGNAL NEWNYM"]
+        );
+        assert!(tor.proxy_url("Site").unwrap().contains(":1@"));
+
+        // rate limited by tor, so not sent again right away
+        assert!(!tor.new_circuit().await.unwrap());
+    }
+}
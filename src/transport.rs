@@ -0,0 +1,205 @@
+use clap::ValueEnum;
+use color_eyre::eyre::{self, WrapErr};
+use reqwest::{Certificate, ClientBuilder, Version};
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use crate::dns::{Nameservers, Resolver};
+
+/// Which HTTP version requests are made with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum HttpVersion {
+    /// Prefer HTTP/2 when the server offers it over TLS, otherwise HTTP/1.1.
+    #[default]
+    Auto,
+    /// Only use HTTP/1.1.
+    Http1,
+    /// Always use HTTP/2, also for plain `http://` URLs.
+    Http2,
+    /// Always use HTTP/3 over QUIC. Needs the `http3` feature and cannot go
+    /// through a proxy.
+    Http3,
+}
+
+/// Protocol, TLS and DNS settings for the HTTP client.
+#[derive(Debug, Clone, Default)]
+pub struct Transport {
+    http_version: HttpVersion,
+    root_certificates: Arc<[Certificate]>,
+    insecure_sites: HashSet<String>,
+    resolver: Option<Arc<Resolver>>,
+    overrides: Arc<[(String, IpAddr)]>,
+}
+
+impl Transport {
+    #[must_use]
+    pub fn new(http_version: HttpVersion) -> Self {
+        Self {
+            http_version,
+            ..Self::default()
+        }
+    }
+
+    /// Trust the certificates in a PEM file in addition to the system's,
+    /// e.g. the CA of a TLS-inspecting proxy.
+    pub fn load_root_certificates(&mut self, path: &str) -> color_eyre::Result<()> {
+        let pem = std::fs::read(path).wrap_err_with(|| {
+            format!("Problem while attempting to access certificate file '{path}'")
+        })?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .wrap_err_with(|| format!("Problem parsing certificate file '{path}'"))?;
+        if certificates.is_empty() {
+            return Err(eyre::eyre!("No certificates found in '{path}'"));
+        }
+
+        self.root_certificates = self
+            .root_certificates
+            .iter()
+            .cloned()
+            .chain(certificates)
+            .collect();
+        Ok(())
+    }
+
+    /// Stop verifying the TLS certificates of these sites.
+    pub fn set_insecure(&mut self, sites: impl IntoIterator<Item = String>) {
+        self.insecure_sites.extend(sites);
+    }
+
+    /// The sites whose certificates are not verified.
+    #[must_use]
+    pub fn insecure_sites(&self) -> &HashSet<String> {
+        &self.insecure_sites
+    }
+
+    /// Look hostnames up with these nameservers instead of the system resolver.
+    /// DNS-over-HTTPS queries are sent through `proxy`.
+    ///
+    /// Call this after loading root certificates, which DNS-over-HTTPS uses too.
+    pub fn set_nameservers(
+        &mut self,
+        nameservers: Nameservers,
+        proxy: Option<&str>,
+    ) -> color_eyre::Result<()> {
+        self.resolver = Some(Arc::new(Resolver::new(
+            nameservers,
+            &self.root_certificates,
+            proxy,
+        )?));
+        Ok(())
+    }
+
+    /// Always connect to `ip` for `host`, without looking it up.
+    pub fn add_override(&mut self, host: String, ip: IpAddr) {
+        self.overrides = self.overrides.iter().cloned().chain([(host, ip)]).collect();
+    }
+
+    /// The settings requests to `site` are made with.
+    #[must_use]
+    pub fn for_site(&self, site: &str) -> SiteTransport {
+        SiteTransport {
+            http_version: self.http_version,
+            root_certificates: Arc::clone(&self.root_certificates),
+            insecure: self.insecure_sites.contains(site),
+            resolver: self.resolver.clone(),
+            overrides: Arc::clone(&self.overrides),
+        }
+    }
+}
+
+/// The protocol, TLS and DNS settings for one site.
+#[derive(Debug, Clone, Default)]
+pub struct SiteTransport {
+    pub http_version: HttpVersion,
+    pub root_certificates: Arc<[Certificate]>,
+    /// Accept any certificate, including self-signed and expired ones.
+    pub insecure: bool,
+    pub resolver: Option<Arc<Resolver>>,
+    /// Addresses used for these hosts instead of looking them up.
+    pub overrides: Arc<[(String, IpAddr)]>,
+}
+
+impl SiteTransport {
+    pub(crate) fn configure(
+        &self,
+        builder: ClientBuilder,
+        proxied: bool,
+    ) -> color_eyre::Result<ClientBuilder> {
+        let builder = match self.http_version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+            // QUIC connections are made directly, so a proxy would silently be bypassed
+            HttpVersion::Http3 if proxied => {
+                return Err(eyre::eyre!(
+                    "HTTP/3 requests cannot be sent through a proxy"
+                ))
+            }
+            // reqwest only speaks QUIC with rustls
+            #[cfg(feature = "http3")]
+            HttpVersion::Http3 => builder.use_rustls_tls().http3_prior_knowledge(),
+            #[cfg(not(feature = "http3"))]
+            HttpVersion::Http3 => {
+                return Err(eyre::eyre!(
+                    "HTTP/3 support is not built in, rebuild with the http3 feature"
+                ))
+            }
+        };
+
+        let builder = match &self.resolver {
+            Some(resolver) => builder.dns_resolver(Arc::clone(resolver)),
+            None => builder,
+        };
+        // port 0 is replaced with the port of the URL
+        let builder = self.overrides.iter().fold(builder, |builder, (host, ip)| {
+            builder.resolve(host, SocketAddr::new(*ip, 0))
+        });
+
+        Ok(self
+            .root_certificates
+            .iter()
+            .cloned()
+            .fold(builder, ClientBuilder::add_root_certificate)
+            .danger_accept_invalid_certs(self.insecure))
+    }
+
+    /// The version every request has to be marked with, as reqwest only
+    /// sends requests marked as HTTP/3 over QUIC.
+    pub(crate) fn request_version(&self) -> Option<Version> {
+        (self.http_version == HttpVersion::Http3).then_some(Version::HTTP_3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_site() {
+        let mut transport = Transport::new(HttpVersion::Http1);
+        transport.set_insecure(["Lab".to_string()]);
+
+        assert!(transport.for_site("Lab").insecure);
+        assert!(!transport.for_site("GitHub").insecure);
+        assert_eq!(
+            transport.for_site("GitHub").http_version,
+            HttpVersion::Http1
+        );
+    }
+
+    #[test]
+    fn test_load_root_certificates() {
+        let path = std::env::temp_dir().join("sherlock-transport-test.pem");
+        std::fs::write(&path, "not a certificate\n").unwrap();
+
+        let mut transport = Transport::default();
+        let error = transport
+            .load_root_certificates(path.to_str().unwrap())
+            .unwrap_err();
+        assert!(error.to_string().contains("No certificates found"));
+        assert!(transport
+            .load_root_certificates("/nonexistent/ca.pem")
+            .is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use color_eyre::eyre::{self, WrapErr};
+use rand::seq::SliceRandom;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// How a user-agent profile is chosen for each request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum UserAgentStrategy {
+    /// Use the same profile for every request.
+    Fixed,
+    /// Pick a profile at random for each request.
+    Random,
+    /// Always use the same profile for a site.
+    PerSite,
+}
+
+/// A browser's user agent together with the headers that browser sends with it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawUserAgentProfile")]
+pub struct UserAgentProfile {
+    pub name: String,
+    /// `User-Agent` and the matching headers, such as `Accept` and `sec-ch-ua`.
+    pub headers: HeaderMap,
+}
+
+#[derive(Deserialize)]
+struct RawUserAgentProfile {
+    name: String,
+    #[serde(rename = "userAgent")]
+    user_agent: String,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+}
+
+impl TryFrom<RawUserAgentProfile> for UserAgentProfile {
+    type Error = color_eyre::Report;
+
+    fn try_from(raw: RawUserAgentProfile) -> Result<Self, Self::Error> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_str(&raw.user_agent)?);
+        for (name, value) in raw.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .wrap_err_with(|| format!("Invalid header name '{name}'"))?;
+            let value = HeaderValue::from_str(&value)
+                .wrap_err_with(|| format!("Invalid value for header '{name}'"))?;
+            headers.insert(name, value);
+        }
+
+        Ok(Self {
+            name: raw.name,
+            headers,
+        })
+    }
+}
+
+/// The user-agent profiles requests are sent with.
+#[derive(Debug, Clone)]
+pub struct UserAgents {
+    profiles: Vec<Arc<UserAgentProfile>>,
+    strategy: UserAgentStrategy,
+    /// The profile used by the fixed strategy.
+    fixed: usize,
+}
+
+impl Default for UserAgents {
+    /// The profiles bundled with sherlock, picked at random.
+    fn default() -> Self {
+        let profiles = parse_profiles(include_str!("user_agents.json"))
+            .expect("bundled user-agent profiles should be valid");
+
+        Self {
+            profiles,
+            strategy: UserAgentStrategy::Random,
+            fixed: 0,
+        }
+    }
+}
+
+impl UserAgents {
+    /// Replace the bundled profiles with those from a JSON file.
+    pub fn load(&mut self, path: &str) -> color_eyre::Result<()> {
+        let json = std::fs::read_to_string(path).wrap_err_with(|| {
+            format!("Problem while attempting to access user-agent file '{path}'")
+        })?;
+        self.profiles = parse_profiles(&json)
+            .wrap_err_with(|| format!("Problem parsing user-agent file '{path}'"))?;
+        self.fixed = 0;
+
+        Ok(())
+    }
+
+    pub fn set_strategy(&mut self, strategy: UserAgentStrategy) {
+        self.strategy = strategy;
+    }
+
+    /// Use the profile called `name` for every request.
+    pub fn fix(&mut self, name: &str) -> color_eyre::Result<()> {
+        self.fixed = self
+            .profiles
+            .iter()
+            .position(|profile| profile.name == name)
+            .ok_or_else(|| {
+                let names = self
+                    .profiles
+                    .iter()
+                    .map(|profile| profile.name.as_str())
+                    .collect::<Vec<_>>();
+                eyre::eyre!(
+                    "Unknown user-agent profile '{name}', expected one of: {}",
+                    names.join(", ")
+                )
+            })?;
+        self.strategy = UserAgentStrategy::Fixed;
+
+        Ok(())
+    }
+
+    /// The profile to use for a request to `site`.
+    #[must_use]
+    pub fn pick(&self, site: &str) -> Arc<UserAgentProfile> {
+        let profile = match self.strategy {
+            UserAgentStrategy::Fixed => &self.profiles[self.fixed],
+            UserAgentStrategy::Random => self
+                .profiles
+                .choose(&mut rand::thread_rng())
+                .expect("there is at least one profile"),
+            UserAgentStrategy::PerSite => {
+                let mut hasher = DefaultHasher::new();
+                site.hash(&mut hasher);
+                &self.profiles[hasher.finish() as usize % self.profiles.len()]
+            }
+        };
+
+        Arc::clone(profile)
+    }
+}
+
+fn parse_profiles(json: &str) -> color_eyre::Result<Vec<Arc<UserAgentProfile>>> {
+    let deserializer = &mut serde_json::Deserializer::from_str(json);
+    let profiles: Vec<UserAgentProfile> = serde_path_to_error::deserialize(deserializer)
+        .map_err(|err| eyre::eyre!("{} at [{}]", err.inner(), err.path()))?;
+    if profiles.is_empty() {
+        return Err(eyre::eyre!("No user-agent profiles"));
+    }
+
+    Ok(profiles.into_iter().map(Arc::new).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_profiles() {
+        let mut user_agents = UserAgents::default();
+        user_agents.fix("firefox-linux").unwrap();
+        let profile = user_agents.pick("GitHub");
+        assert!(profile.headers[USER_AGENT]
+            .to_str()
+            .unwrap()
+            .contains("Firefox"));
+        assert!(!profile.headers.contains_key("sec-ch-ua"));
+
+        assert!(user_agents.fix("netscape").is_err());
+
+        user_agents.set_strategy(UserAgentStrategy::PerSite);
+        let name = user_agents.pick("GitHub").name.clone();
+        assert!((0..10).all(|_| user_agents.pick("GitHub").name == name));
+    }
+
+    #[test]
+    fn test_invalid_profiles() {
+        assert!(parse_profiles("[]").is_err());
+        assert!(parse_profiles(
+            r#"[{"name": "bad", "userAgent": "x", "headers": {"bad header": "x"}}]"#
+        )
+        .is_err());
+    }
+}
@@ -1,4 +1,8 @@
+use serde_json::Value;
 use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 
 ///
 /// Creates username variants by replacing the variant symbol with check symbols.
@@ -132,6 +136,89 @@ impl<T: Interpolatable> Interpolatable for HashMap<String, T> {
     }
 }
 
+impl Interpolatable for Value {
+    /// Interpolates the given text into every string inside the JSON value.
+    ///
+    /// # Arguments
+    /// * `text` - The text to interpolate.
+    ///
+    /// # Returns
+    /// The interpolated JSON value.
+    ///
+    /// # Example
+    /// ```
+    /// use serde_json::json;
+    /// use sherlock::utils::Interpolatable;
+    ///
+    /// let payload = json!({"query": "user(login: \"{}\")", "limit": 1});
+    /// let interpolated_payload = payload.interpolate("test");
+    ///
+    /// assert_eq!(interpolated_payload, json!({"query": "user(login: \"test\")", "limit": 1}));
+    /// ```
+    fn interpolate(&self, text: &str) -> Self {
+        match self {
+            Value::String(string) => Value::String(string.interpolate(text)),
+            Value::Array(values) => Value::Array(values.interpolate(text)),
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), value.interpolate(text)))
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+}
+
+///
+/// Writes a file atomically by writing to a temporary sibling file first
+/// and renaming it into place once everything has been flushed to disk.
+/// An interrupted run never leaves a half-written file behind.
+///
+/// # Arguments
+/// * `path` - The final path of the file.
+/// * `write` - Closure that writes the file contents.
+///
+/// # Returns
+/// A Result containing the success or failure of the operation.
+///
+/// # Example
+/// ```
+/// use std::io::Write;
+/// use sherlock::utils::write_atomic;
+///
+/// let path = std::env::temp_dir().join("sherlock_write_atomic_doctest.txt");
+/// write_atomic(&path, |file| writeln!(file, "hello")).unwrap();
+///
+/// assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello\n");
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn write_atomic<P, F>(path: P, write: F) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let path = path.as_ref();
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let tmp_path = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+
+    let result = File::create(&tmp_path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()
+    });
+
+    match result {
+        Ok(()) => fs::rename(&tmp_path, path),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -177,4 +264,24 @@ mod tests {
         let result = create_username_variants(&usernames);
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_write_atomic_leaves_no_partial_file() {
+        let dir = std::env::temp_dir().join(format!("sherlock_atomic_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.txt");
+
+        let result = write_atomic(&path, |file| {
+            writeln!(file, "partial")?;
+            Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"))
+        });
+        assert!(result.is_err());
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        write_atomic(&path, |file| writeln!(file, "complete")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "complete\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
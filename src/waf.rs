@@ -1,15 +1,222 @@
 // As WAFs advance and evolve, they will occasionally block Sherlock and
 // lead to false positives and negatives. Fingerprints should be added
-// here to filter results that fail to bypass WAFs. Fingerprints should
-// be highly targetted. Comment at the end of each fingerprint to
-// indicate target and date fingerprinted.
-const WAFHIT_MSGS: [&str; 2] = [
-    r".loading-spinner{visibility:hidden}body.no-js .challenge-running{display:none}body.dark{background-color:#222;color:#d9d9d9}body.dark a{color:#fff}body.dark a:hover{color:#ee730a;text-decoration:underline}body.dark .lds-ring div{border-color:#999 transparent transparent}body.dark .font-red{color:#b20f03}body.dark",
-    // 2024-04-09 PerimeterX / Human Security
-    r#"{return l.onPageView}}),Object.defineProperty(r,"perimeterxIdentifiers",{enumerable:"#,
-];
-
-#[must_use]
-pub fn waf_hit(resp_text: &str) -> bool {
-    WAFHIT_MSGS.iter().any(|msg| resp_text.contains(msg))
+// to waf.json, or to a fingerprint file passed with --waf-fingerprints,
+// to filter results that fail to bypass WAFs. Fingerprints should be
+// highly targetted. Use `__comment__` on each fingerprint to indicate
+// target and date fingerprinted. Markers that are too broad for a
+// fingerprint belong in `hints`, which only lower confidence.
+
+use crate::sherlock_target_manifest::{ErrorCode, ErrorMsg};
+use color_eyre::eyre::{self, WrapErr};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A fingerprint of a response served by a WAF or bot challenge instead of the real page.
+///
+/// Every condition that is set must match. A fingerprint must look at the
+/// headers or the body, since a status code alone is too broad.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WafFingerprint {
+    /// The vendor reported when this fingerprint matches, e.g. `Cloudflare`.
+    pub vendor: String,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        rename = "statusCode",
+        default
+    )]
+    pub status_code: Option<ErrorCode>,
+    /// Response headers that must be present, with text or a regex their value must match.
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    pub headers: HashMap<String, ErrorMsg>,
+    /// Text or regexes of which at least one must appear in the body.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub body: Option<ErrorMsg>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub __comment__: Option<String>,
+}
+
+impl WafFingerprint {
+    #[must_use]
+    pub fn matches(&self, status_code: u16, headers: &HeaderMap, body: &str) -> bool {
+        let status_matches = self
+            .status_code
+            .as_ref()
+            .is_none_or(|codes| codes.contains(&status_code));
+        let headers_match = self.headers.iter().all(|(name, pattern)| {
+            headers
+                .get_all(name.as_str())
+                .iter()
+                .any(|value| pattern.is_in(&String::from_utf8_lossy(value.as_bytes())))
+        });
+        let body_matches = self.body.as_ref().is_none_or(|pattern| pattern.is_in(body));
+
+        status_matches && headers_match && body_matches
+    }
+}
+
+/// A fingerprint file: fingerprints and weaker hints of a bot challenge.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WafFile {
+    #[serde(default)]
+    fingerprints: Vec<WafFingerprint>,
+    /// Markers of bot challenges and captcha pages that are too broad to
+    /// call a response blocked, but make a verdict less trustworthy.
+    #[serde(default)]
+    hints: Option<ErrorMsg>,
+}
+
+/// The set of fingerprints and hints responses are checked against.
+#[derive(Debug, Clone)]
+pub struct WafDatabase {
+    fingerprints: Vec<WafFingerprint>,
+    hints: Vec<ErrorMsg>,
+}
+
+impl Default for WafDatabase {
+    /// The fingerprints and hints bundled with sherlock.
+    fn default() -> Self {
+        let file = parse_waf_file(include_str!("waf.json"))
+            .expect("bundled WAF fingerprints should be valid");
+
+        Self {
+            fingerprints: file.fingerprints,
+            hints: file.hints.into_iter().collect(),
+        }
+    }
+}
+
+impl WafDatabase {
+    /// Add the fingerprints and hints from a JSON file to the database.
+    pub fn load(&mut self, path: &str) -> color_eyre::Result<()> {
+        let json = std::fs::read_to_string(path).wrap_err_with(|| {
+            format!("Problem while attempting to access WAF fingerprint file '{path}'")
+        })?;
+        let file = parse_waf_file(&json)
+            .wrap_err_with(|| format!("Problem parsing WAF fingerprint file '{path}'"))?;
+        self.fingerprints.extend(file.fingerprints);
+        self.hints.extend(file.hints);
+
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn fingerprints(&self) -> &[WafFingerprint] {
+        &self.fingerprints
+    }
+
+    /// The vendor of the first fingerprint matching the response, if any.
+    #[must_use]
+    pub fn detect(&self, status_code: u16, headers: &HeaderMap, body: &str) -> Option<&str> {
+        self.fingerprints
+            .iter()
+            .find(|fingerprint| fingerprint.matches(status_code, headers, body))
+            .map(|fingerprint| fingerprint.vendor.as_str())
+    }
+
+    /// Whether a body looks like it may have been served by a bot challenge
+    /// without matching a full fingerprint.
+    #[must_use]
+    pub fn suspect(&self, body: &str) -> bool {
+        self.hints.iter().any(|hint| hint.is_in(body))
+    }
+}
+
+fn parse_waf_file(json: &str) -> color_eyre::Result<WafFile> {
+    let deserializer = &mut serde_json::Deserializer::from_str(json);
+    let file: WafFile = serde_path_to_error::deserialize(deserializer)
+        .map_err(|err| eyre::eyre!("{} at [{}]", err.inner(), err.path()))?;
+
+    if let Some(fingerprint) = file
+        .fingerprints
+        .iter()
+        .find(|fingerprint| fingerprint.headers.is_empty() && fingerprint.body.is_none())
+    {
+        return Err(eyre::eyre!(
+            "WAF fingerprint for '{}' must match on headers or body",
+            fingerprint.vendor
+        ));
+    }
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_bundled_fingerprints() {
+        let waf = WafDatabase::default();
+        let mut headers = HeaderMap::new();
+
+        assert_eq!(waf.detect(200, &headers, "<h1>alice</h1>"), None);
+
+        headers.insert("cf-mitigated", HeaderValue::from_static("challenge"));
+        assert_eq!(waf.detect(403, &headers, ""), Some("Cloudflare"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("server", HeaderValue::from_static("cloudflare"));
+        assert_eq!(waf.detect(200, &headers, "<h1>alice</h1>"), None);
+        assert_eq!(
+            waf.detect(503, &headers, "<title>Just a moment...</title>"),
+            Some("Cloudflare")
+        );
+
+        let headers = HeaderMap::new();
+        assert_eq!(
+            waf.detect(200, &headers, "Incapsula incident ID: 123"),
+            Some("Imperva")
+        );
+        assert_eq!(
+            waf.detect(403, &headers, r#"<div class="g-recaptcha">"#),
+            Some("Captcha")
+        );
+        assert_eq!(
+            waf.detect(200, &headers, r#"<div class="g-recaptcha">"#),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_needs_headers_or_body() {
+        assert!(parse_waf_file(
+            r#"{"fingerprints": [{"vendor": "Too broad", "statusCode": 403}]}"#
+        )
+        .is_err());
+        assert!(
+            parse_waf_file(r#"{"fingerprints": [{"vendor": "Ok", "body": "blocked"}]}"#).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_akamai_cookie_alone_is_not_blocked() {
+        let waf = WafDatabase::default();
+        let headers = HeaderMap::new();
+        let page = r#"<script>document.cookie = "_abck=123";</script><h1>alice</h1>"#;
+
+        assert_eq!(waf.detect(200, &headers, page), None);
+        assert_eq!(waf.detect(403, &headers, page), Some("Akamai"));
+    }
+
+    #[test]
+    fn test_hints() {
+        let mut waf = WafDatabase::default();
+        assert!(waf.suspect("please solve the captcha"));
+        assert!(!waf.suspect("<h1>alice</h1>"));
+
+        let path = std::env::temp_dir().join("sherlock-waf-hints-test.json");
+        std::fs::write(
+            &path,
+            r#"{"hints": ["verify you are human", {"regex": "shield-\\d+"}]}"#,
+        )
+        .unwrap();
+        let loaded = waf.load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        loaded.unwrap();
+        assert!(waf.suspect("please verify you are human"));
+        assert!(waf.suspect("<div id=shield-42>"));
+    }
 }
@@ -0,0 +1,103 @@
+mod common;
+
+use common::{check_options, check_with, target};
+use serde_json::json;
+use sherlock::{cassette::Cassette, checker::CheckOptions, query::QueryStatus};
+use std::sync::Arc;
+use wiremock::matchers::path;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn replay_reaches_recorded_verdicts() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<h1>alice</h1>"))
+        .mount(&server)
+        .await;
+    Mock::given(path("/waf/alice"))
+        .respond_with(
+            ResponseTemplate::new(403)
+                .insert_header("server", "cloudflare")
+                .insert_header("cf-ray", "8a1b2c3d4e5f-AMS")
+                .set_body_string("<title>Just a moment...</title>"),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(path("/binary/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![0xff, 0xfe, 0x00]))
+        .mount(&server)
+        .await;
+
+    let sites = json!({
+        "Claimed": target(&server, "/{}", json!({ "errorType": "message", "errorMsg": "Not found" })),
+        "Available": target(&server, "/missing/{}", json!({ "errorType": "status_code" })),
+        "Waf": target(&server, "/waf/{}", json!({ "errorType": "message", "errorMsg": "Not found" })),
+        "Binary": target(&server, "/binary/{}", json!({ "errorType": "message", "errorMsg": "Not found" })),
+    });
+    let path = std::env::temp_dir().join(format!("sherlock-cassette-{}.json", std::process::id()));
+
+    let recorder = Arc::new(Cassette::record(&path, 1024 * 1024));
+    let options = CheckOptions {
+        cassette: Some(Arc::clone(&recorder)),
+        ..check_options()
+    };
+    let recorded = check_with("alice", sites.clone(), &options).await;
+    recorder.save().unwrap();
+    drop(server);
+
+    let options = CheckOptions {
+        cassette: Some(Arc::new(Cassette::replay(&path).unwrap())),
+        ..check_options()
+    };
+    let replayed = check_with("alice", sites, &options).await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(recorded["Claimed"].status, QueryStatus::Claimed);
+    assert_eq!(recorded["Available"].status, QueryStatus::Available);
+    assert_eq!(recorded["Waf"].status, QueryStatus::Waf);
+    for (site, result) in &recorded {
+        assert_eq!(replayed[site].status, result.status, "{site}");
+        assert_eq!(replayed[site].http_status, result.http_status, "{site}");
+    }
+}
+
+#[tokio::test]
+async fn recorded_bodies_are_capped() {
+    let server = MockServer::start().await;
+    let body = format!("{}User not found", "x".repeat(64 * 1024));
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(body))
+        .mount(&server)
+        .await;
+
+    let sites = json!({
+        "Site": target(&server, "/{}", json!({ "errorType": "message", "errorMsg": "User not found" })),
+    });
+    let path = std::env::temp_dir().join(format!(
+        "sherlock-cassette-capped-{}.json",
+        std::process::id()
+    ));
+
+    let recorder = Arc::new(Cassette::record(&path, 64));
+    let options = CheckOptions {
+        cassette: Some(Arc::clone(&recorder)),
+        max_body_size: 64,
+        ..check_options()
+    };
+    let recorded = check_with("alice", sites.clone(), &options).await;
+    recorder.save().unwrap();
+    drop(server);
+
+    let options = CheckOptions {
+        cassette: Some(Arc::new(Cassette::replay(&path).unwrap())),
+        ..options
+    };
+    let replayed = check_with("alice", sites, &options).await;
+    let saved = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(saved.len() < 4 * 1024, "{} bytes saved", saved.len());
+    assert_eq!(recorded["Site"].status, QueryStatus::Unknown);
+    assert_eq!(replayed["Site"].status, QueryStatus::Unknown);
+    assert_eq!(replayed["Site"].context, recorded["Site"].context);
+}
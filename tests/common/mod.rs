@@ -0,0 +1,103 @@
+//! Shared harness for the detection tests.
+//!
+//! Each test starts a [`MockServer`] serving scripted responses, builds a
+//! manifest whose targets point at it and runs the real checker over it.
+
+#![allow(dead_code)]
+
+use serde_json::{json, Value};
+use sherlock::{
+    checker::{check_username, CheckOptions},
+    credentials::Credentials,
+    query::QueryResult,
+    sherlock_target_manifest::{SherlockTargetManifest, TargetInfo},
+    transport::Transport,
+    user_agent::UserAgents,
+    waf::WafDatabase,
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use wiremock::MockServer;
+
+/// Options that keep the checker quiet and fast.
+pub fn check_options() -> CheckOptions {
+    CheckOptions {
+        timeout: Duration::from_secs(5),
+        proxy: None,
+        print_all: false,
+        print_found: false,
+        dump: None,
+        browse: false,
+        progress: false,
+        cassette: None,
+        min_confidence: 0.0,
+        site_reliability: None,
+        waf: Arc::new(WafDatabase::default()),
+        max_body_size: 1024 * 1024,
+        tor: None,
+        proxy_pool: None,
+        routing: None,
+        user_agents: Arc::new(UserAgents::default()),
+        cookies: None,
+        credentials: Arc::new(Credentials::default()),
+        transport: Arc::new(Transport::default()),
+        retries: 0,
+    }
+}
+
+/// A manifest entry for `server` with the given detection fields.
+///
+/// `path` is appended to the server URI and should contain `{}` where the
+/// username goes. `fields` is merged into the entry, e.g.
+/// `json!({"errorType": "status_code"})`.
+pub fn target(server: &MockServer, path: &str, fields: Value) -> Value {
+    let mut entry = json!({
+        "url": format!("{}{}", server.uri(), path),
+        "urlMain": server.uri(),
+        "username_claimed": "alice",
+    });
+
+    let entry_map = entry.as_object_mut().unwrap();
+    for (key, value) in fields.as_object().expect("fields must be an object") {
+        entry_map.insert(key.clone(), value.clone());
+    }
+
+    entry
+}
+
+/// Deserialize `sites` as a manifest the same way the CLI does.
+pub fn manifest(sites: Value) -> Arc<HashMap<String, Arc<TargetInfo>>> {
+    let mut data = json!({ "$schema": "data.schema.json" });
+    for (site, entry) in sites.as_object().expect("sites must be an object") {
+        data[site] = entry.clone();
+    }
+
+    let manifest: SherlockTargetManifest =
+        serde_json::from_value(data).expect("generated manifest should deserialize");
+
+    Arc::new(
+        manifest
+            .targets
+            .into_iter()
+            .map(|(site, info)| (site, Arc::new(info)))
+            .collect(),
+    )
+}
+
+/// Check `username` against `sites` and index the results by site name.
+pub async fn check(username: &str, sites: Value) -> HashMap<String, QueryResult> {
+    check_with(username, sites, &check_options()).await
+}
+
+/// Like [`check`], with custom checker options.
+pub async fn check_with(
+    username: &str,
+    sites: Value,
+    options: &CheckOptions,
+) -> HashMap<String, QueryResult> {
+    check_username(username, manifest(sites), options)
+        .await
+        .expect("check should complete")
+        .into_iter()
+        .map(|result| (result.site_name.to_string(), result))
+        .collect()
+}
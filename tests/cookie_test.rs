@@ -0,0 +1,93 @@
+mod common;
+
+use common::{check, check_options, check_with, target};
+use serde_json::json;
+use sherlock::{checker::CheckOptions, cookies::CookieJars, query::QueryStatus};
+use std::sync::Arc;
+use wiremock::matchers::{header, header_regex, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn with_cookies(cookies: CookieJars) -> CheckOptions {
+    CheckOptions {
+        cookies: Some(Arc::new(cookies)),
+        ..check_options()
+    }
+}
+
+#[tokio::test]
+async fn consent_cookie_survives_redirect() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .and(header("cookie", "consent=yes"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(302).insert_header("location", "/consent"))
+        .mount(&server)
+        .await;
+    Mock::given(path("/consent"))
+        .respond_with(
+            ResponseTemplate::new(302)
+                .insert_header("set-cookie", "consent=yes; Path=/")
+                .insert_header("location", "/alice"),
+        )
+        .mount(&server)
+        .await;
+
+    let sites = json!({
+        "Site": target(&server, "/{}", json!({ "errorType": "status_code" })),
+    });
+
+    // without a jar the redirects loop until they give up
+    assert_eq!(
+        check("alice", sites.clone()).await["Site"].status,
+        QueryStatus::Unknown
+    );
+    assert_eq!(
+        check_with("alice", sites, &with_cookies(CookieJars::default())).await["Site"].status,
+        QueryStatus::Claimed
+    );
+}
+
+#[tokio::test]
+async fn loaded_cookies_join_manifest_cookies() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .and(header_regex("cookie", "session=abc"))
+        .and(header_regex("cookie", "lang=en"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    let file = std::env::temp_dir().join(format!("cookies-{}.json", std::process::id()));
+    std::fs::write(
+        &file,
+        json!([{ "domain": "127.0.0.1", "hostOnly": true, "name": "session", "value": "abc" }])
+            .to_string(),
+    )
+    .unwrap();
+    let mut cookies = CookieJars::default();
+    let loaded = cookies.load(file.to_str().unwrap());
+    std::fs::remove_file(&file).unwrap();
+    loaded.unwrap();
+
+    let results = check_with(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({ "errorType": "status_code", "headers": { "Cookie": "lang=en" } }),
+            ),
+        }),
+        &with_cookies(cookies),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
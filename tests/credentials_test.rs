@@ -0,0 +1,149 @@
+mod common;
+
+use common::{check, check_options, check_with, target};
+use serde_json::json;
+use sherlock::{cassette::Cassette, checker::CheckOptions, dump::DumpOptions, query::QueryStatus};
+use std::sync::Arc;
+use wiremock::matchers::{header, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// A site that only answers with the right key in the probe URL and bearer token.
+async fn authed_api() -> (MockServer, serde_json::Value) {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .and(header("authorization", "Bearer s3cr3t-token"))
+        .and(query_param("key", "s3cr3t-key"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    // site names are unique to this fixture, as the environment is shared
+    std::env::set_var("SHERLOCK_CREDENTIAL_AUTHED_API_BEARER", "s3cr3t-token");
+    std::env::set_var("SHERLOCK_CREDENTIAL_AUTHED_API_API_KEY", "s3cr3t-key");
+
+    let sites = json!({
+        "Authed API": target(&server, "/{}", json!({
+            "errorType": "status_code",
+            "urlProbe": format!("{}/{{}}?key={{credential.apiKey}}", server.uri()),
+            "headers": { "Authorization": "Bearer {credential.bearer}" },
+        })),
+    });
+    (server, sites)
+}
+
+#[tokio::test]
+async fn credentials_are_sent_but_not_dumped() {
+    let (_server, sites) = authed_api().await;
+
+    let dir = std::env::temp_dir().join(format!("credential-dumps-{}", std::process::id()));
+    let dump = DumpOptions {
+        dir: dir.clone(),
+        sites: Vec::new(),
+        statuses: Vec::new(),
+    };
+    let options = CheckOptions {
+        dump: Some(dump.clone()),
+        ..check_options()
+    };
+
+    let results = check_with("alice", sites, &options).await;
+
+    assert_eq!(results["Authed API"].status, QueryStatus::Claimed);
+    let dumped = std::fs::read_to_string(dump.path_for("alice", "Authed API")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(dumped.contains("Bearer [REDACTED]"));
+    assert!(!dumped.contains("s3cr3t"));
+}
+
+#[tokio::test]
+async fn credentials_are_not_recorded() {
+    let (_server, sites) = authed_api().await;
+
+    let path =
+        std::env::temp_dir().join(format!("credential-cassette-{}.json", std::process::id()));
+    let recorder = Arc::new(Cassette::record(&path, 1024 * 1024));
+    let options = CheckOptions {
+        cassette: Some(Arc::clone(&recorder)),
+        ..check_options()
+    };
+
+    let results = check_with("alice", sites, &options).await;
+    recorder.save().unwrap();
+
+    assert_eq!(results["Authed API"].status, QueryStatus::Claimed);
+    let recorded = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(recorded.contains("key=[REDACTED]"));
+    assert!(!recorded.contains("s3cr3t"));
+}
+
+#[tokio::test]
+async fn missing_credential_is_unknown() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Unconfigured": target(&server, "/{}", json!({
+                "errorType": "status_code",
+                "headers": { "X-Api-Key": "{credential.apiKey}" },
+            })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Unconfigured"].status, QueryStatus::Unknown);
+    assert_eq!(
+        results["Unconfigured"].context.as_deref(),
+        Some("No apiKey credential configured for this site")
+    );
+}
+
+#[tokio::test]
+async fn credentials_are_trimmed_and_invalid_ones_are_unknown() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .and(header("authorization", "Bearer s3cr3t-token"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    std::env::set_var("SHERLOCK_CREDENTIAL_PASTED_BEARER", "s3cr3t-token\n");
+    std::env::set_var("SHERLOCK_CREDENTIAL_BROKEN_BEARER", "s3cr3t\ntoken");
+
+    let site = target(
+        &server,
+        "/{}",
+        json!({
+            "errorType": "status_code",
+            "headers": { "Authorization": "Bearer {credential.bearer}" },
+        }),
+    );
+    let results = check("alice", json!({ "Pasted": site, "Broken": site })).await;
+
+    assert_eq!(results["Pasted"].status, QueryStatus::Claimed);
+    assert_eq!(results["Broken"].status, QueryStatus::Unknown);
+}
+
+#[tokio::test]
+async fn usernames_cannot_ask_for_credentials() {
+    let (server, sites) = authed_api().await;
+
+    let results = check("{credential.apiKey}", sites).await;
+
+    assert_eq!(results["Authed API"].status, QueryStatus::Illegal);
+    assert!(server.received_requests().await.unwrap().is_empty());
+}
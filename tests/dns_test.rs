@@ -0,0 +1,257 @@
+mod common;
+
+use common::{check_options, check_with};
+use serde_json::json;
+use sherlock::{checker::CheckOptions, dns::Nameservers, query::QueryStatus, transport::Transport};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// A DNS response with the given ID and one A record for 127.0.0.1.
+fn answer(id: [u8; 2]) -> Vec<u8> {
+    let mut message = id.to_vec();
+    message.extend_from_slice(&[0x81, 0x80, 0, 0, 0, 1, 0, 0, 0, 0]);
+    message.extend_from_slice(&[0, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 1]);
+    message
+}
+
+/// The response to a DNS `query`, with `address` as the answer to A queries.
+fn respond(query: &[u8], id: [u8; 2], truncated: bool, address: [u8; 4]) -> Vec<u8> {
+    // the question ends after its name and the type and class
+    let mut end = 12;
+    while query[end] != 0 {
+        end += 1 + query[end] as usize;
+    }
+    let question = &query[12..end + 5];
+    let is_a = question[question.len() - 4..question.len() - 2] == [0, 1];
+
+    let mut message = id.to_vec();
+    message.extend_from_slice(&[if truncated { 0x83 } else { 0x81 }, 0x80]);
+    message.extend_from_slice(&[0, 1, 0, u8::from(is_a && !truncated), 0, 0, 0, 0]);
+    message.extend_from_slice(question);
+    if is_a && !truncated {
+        message.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+        message.extend_from_slice(&address);
+    }
+    message
+}
+
+/// A DNS server answering every A query with 127.0.0.1 over UDP, first
+/// sending a stray answer with the wrong ID if `stray` is set.
+async fn dns_server(stray: bool) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0; 512];
+        while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+            let query = &buf[..len];
+            let id = [query[0], query[1]];
+            if stray {
+                // an unroutable address, so using it would fail the check
+                let other = [id[0] ^ 0xff, id[1]];
+                let _ = socket
+                    .send_to(&respond(query, other, false, [192, 0, 2, 1]), peer)
+                    .await;
+            }
+            let _ = socket
+                .send_to(&respond(query, id, false, [127, 0, 0, 1]), peer)
+                .await;
+        }
+    });
+
+    address
+}
+
+/// A DNS server whose UDP answers are all truncated, answering A queries
+/// with 127.0.0.1 over TCP only.
+async fn truncating_dns_server() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0; 512];
+        while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+            let query = &buf[..len];
+            let response = respond(query, [query[0], query[1]], true, [127, 0, 0, 1]);
+            let _ = socket.send_to(&response, peer).await;
+        }
+    });
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                // every message over TCP starts with its length
+                while let Ok(len) = stream.read_u16().await {
+                    let mut query = vec![0; len as usize];
+                    if stream.read_exact(&mut query).await.is_err() {
+                        return;
+                    }
+                    let response = respond(&query, [query[0], query[1]], false, [127, 0, 0, 1]);
+                    let _ = stream.write_u16(response.len() as u16).await;
+                    let _ = stream.write_all(&response).await;
+                }
+            });
+        }
+    });
+
+    address
+}
+
+async fn claimed_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    server
+}
+
+/// A site on an unresolvable host, served by `server`.
+fn site(host: &str, server: &MockServer) -> serde_json::Value {
+    let url = format!("http://{host}:{}/{{}}", server.address().port());
+    json!({
+        "url": url,
+        "urlMain": url,
+        "username_claimed": "alice",
+        "errorType": "status_code",
+    })
+}
+
+fn options(transport: Transport) -> CheckOptions {
+    CheckOptions {
+        transport: Arc::new(transport),
+        ..check_options()
+    }
+}
+
+#[tokio::test]
+async fn overrides_are_used_instead_of_dns() {
+    let server = claimed_server().await;
+    let mut transport = Transport::default();
+    transport.add_override(
+        "mirror.example.invalid".to_string(),
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+    );
+
+    let results = check_with(
+        "alice",
+        json!({ "Mirror": site("mirror.example.invalid", &server) }),
+        &options(transport),
+    )
+    .await;
+
+    assert_eq!(results["Mirror"].status, QueryStatus::Claimed);
+}
+
+async fn resolved_with(server: &MockServer, nameservers: Nameservers) -> QueryStatus {
+    let mut transport = Transport::default();
+    transport.set_nameservers(nameservers, None).unwrap();
+
+    let results = check_with(
+        "alice",
+        // .invalid names never reach the nameservers, so the reserved .test is used
+        json!({ "Lab": site("lab.example.test", server) }),
+        &options(transport),
+    )
+    .await;
+    results["Lab"].status
+}
+
+#[tokio::test]
+async fn hosts_are_resolved_with_configured_servers() {
+    let server = claimed_server().await;
+    let nameservers = Nameservers::Udp(vec![dns_server(false).await]);
+
+    assert_eq!(
+        resolved_with(&server, nameservers).await,
+        QueryStatus::Claimed
+    );
+}
+
+#[tokio::test]
+async fn answers_with_another_id_are_ignored() {
+    let server = claimed_server().await;
+    let nameservers = Nameservers::Udp(vec![dns_server(true).await]);
+
+    assert_eq!(
+        resolved_with(&server, nameservers).await,
+        QueryStatus::Claimed
+    );
+}
+
+#[tokio::test]
+async fn truncated_answers_are_retried_over_tcp() {
+    let server = claimed_server().await;
+    let nameservers = Nameservers::Udp(vec![truncating_dns_server().await]);
+
+    assert_eq!(
+        resolved_with(&server, nameservers).await,
+        QueryStatus::Claimed
+    );
+}
+
+#[tokio::test]
+async fn hosts_are_resolved_over_https() {
+    let server = claimed_server().await;
+    let doh = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/dns-query"))
+        .and(header("content-type", "application/dns-message"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("content-type", "application/dns-message")
+                .set_body_bytes(answer([0, 0])),
+        )
+        .expect(2)
+        .mount(&doh)
+        .await;
+
+    let mut transport = Transport::default();
+    let url = format!("{}/dns-query", doh.uri()).parse().unwrap();
+    transport
+        .set_nameservers(Nameservers::Https(url), None)
+        .unwrap();
+
+    let results = check_with(
+        "alice",
+        json!({ "Lab": site("lab.example.invalid", &server) }),
+        &options(transport),
+    )
+    .await;
+
+    assert_eq!(results["Lab"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn https_lookups_go_through_the_proxy() {
+    let server = claimed_server().await;
+    let proxy = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/dns-query"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("content-type", "application/dns-message")
+                .set_body_bytes(answer([0, 0])),
+        )
+        .expect(2)
+        .mount(&proxy)
+        .await;
+
+    // only the proxy can reach this DNS-over-HTTPS server
+    let url = "http://doh.example.invalid/dns-query".parse().unwrap();
+    let mut transport = Transport::default();
+    transport
+        .set_nameservers(Nameservers::Https(url), Some(&proxy.uri()))
+        .unwrap();
+
+    let results = check_with(
+        "alice",
+        json!({ "Lab": site("lab.example.invalid", &server) }),
+        &options(transport),
+    )
+    .await;
+
+    assert_eq!(results["Lab"].status, QueryStatus::Claimed);
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::{check, target};
+use serde_json::json;
+use sherlock::query::QueryStatus;
+use wiremock::matchers::path;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn absent_if_requires_status_and_message() {
+    let server = MockServer::start().await;
+    Mock::given(path("/nobody"))
+        .respond_with(ResponseTemplate::new(404).set_body_string("No such user"))
+        .mount(&server)
+        .await;
+    Mock::given(path("/maintenance"))
+        .respond_with(ResponseTemplate::new(404).set_body_string("Down for maintenance"))
+        .mount(&server)
+        .await;
+
+    let rules = json!({
+        "errorType": "rules",
+        "absentIf": { "statusCode": 404, "message": "No such user" },
+    });
+
+    let results = check(
+        "nobody",
+        json!({ "Site": target(&server, "/{}", rules.clone()) }),
+    )
+    .await;
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+
+    let results = check(
+        "maintenance",
+        json!({ "Site": target(&server, "/{}", rules) }),
+    )
+    .await;
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn username_in_body_is_claimed() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<title>alice on Site</title>"))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({
+                    "errorType": "status_code",
+                    "claimedIf": { "statusCode": 200, "usernameInBody": true },
+                }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn soft_404_without_presence_is_unknown() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<title>Log in</title>"))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({
+                    "errorType": "status_code",
+                    "claimedIf": { "usernameInBody": true },
+                }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Unknown);
+}
+
+#[tokio::test]
+async fn json_field_presence() {
+    let server = MockServer::start().await;
+    Mock::given(path("/api/alice"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "data": { "user": { "id": 1 } } })),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(path("/api/nobody"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": { "user": null } })))
+        .mount(&server)
+        .await;
+    Mock::given(path("/api/captcha"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<html>captcha</html>"))
+        .mount(&server)
+        .await;
+
+    let rules = json!({
+        "errorType": "rules",
+        "absentIf": { "message": "\"user\":null" },
+        "claimedIf": { "jsonField": "$.data.user.id" },
+    });
+
+    let results = check(
+        "alice",
+        json!({ "Site": target(&server, "/api/{}", rules.clone()) }),
+    )
+    .await;
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+
+    let results = check(
+        "nobody",
+        json!({ "Site": target(&server, "/api/{}", rules.clone()) }),
+    )
+    .await;
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+
+    let results = check(
+        "captcha",
+        json!({ "Site": target(&server, "/api/{}", rules) }),
+    )
+    .await;
+    assert_eq!(results["Site"].status, QueryStatus::Unknown);
+}
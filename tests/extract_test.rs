@@ -0,0 +1,71 @@
+mod common;
+
+use common::{check, target};
+use serde_json::json;
+use sherlock::query::QueryStatus;
+use wiremock::matchers::path;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const PROFILE: &str = r#"<html><body>
+<h1 class="display-name">Alice Example</h1>
+<p class="bio">Rustacean &amp; birder</p>
+<img class="avatar" src="/avatars/alice.png">
+<span class="followers">1,024 followers</span>
+<a rel="me" href="https://social.example/@alice">social</a>
+</body></html>"#;
+
+fn extract_rules(server: &MockServer) -> serde_json::Value {
+    target(
+        server,
+        "/{}",
+        json!({
+            "errorType": "status_code",
+            "extract": {
+                "name": { "css": "h1.display-name" },
+                "bio": { "css": "p.bio" },
+                "avatar": { "css": "img.avatar", "attr": "src" },
+                "followers": { "regex": "([0-9,]+) followers" },
+                "links": { "css": "a[rel=me]", "attr": "href", "all": true },
+            },
+        }),
+    )
+}
+
+#[tokio::test]
+async fn claimed_profile_metadata_is_extracted() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(PROFILE))
+        .mount(&server)
+        .await;
+
+    let results = check("alice", json!({ "Site": extract_rules(&server) })).await;
+    let result = &results["Site"];
+
+    assert_eq!(result.status, QueryStatus::Claimed);
+    assert_eq!(result.metadata["name"], vec!["Alice Example"]);
+    assert_eq!(result.metadata["bio"], vec!["Rustacean & birder"]);
+    assert_eq!(
+        result.metadata["avatar"],
+        vec![format!("{}/avatars/alice.png", server.uri())]
+    );
+    assert_eq!(result.metadata["followers"], vec!["1,024"]);
+    assert_eq!(
+        result.metadata["links"],
+        vec!["https://social.example/@alice"]
+    );
+}
+
+#[tokio::test]
+async fn available_profile_has_no_metadata() {
+    let server = MockServer::start().await;
+    Mock::given(path("/nobody"))
+        .respond_with(ResponseTemplate::new(404).set_body_string(PROFILE))
+        .mount(&server)
+        .await;
+
+    let results = check("nobody", json!({ "Site": extract_rules(&server) })).await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+    assert!(results["Site"].metadata.is_empty());
+}
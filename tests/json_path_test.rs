@@ -0,0 +1,77 @@
+mod common;
+
+use common::{check, target};
+use serde_json::json;
+use sherlock::query::QueryStatus;
+use wiremock::matchers::path;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+async fn api_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(path("/api/alice"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "data": { "user": { "id": 7 } } })),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(path("/api/nobody"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": { "user": null } })))
+        .mount(&server)
+        .await;
+    Mock::given(path("/api/gone"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "errors": [{ "code": 404 }] })),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(path("/api/blocked"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<html>Access denied</html>"))
+        .mount(&server)
+        .await;
+    server
+}
+
+#[tokio::test]
+async fn null_field_is_available() {
+    let server = api_server().await;
+    let rule = json!({ "errorType": "json_path", "errorPath": "$.data.user == null" });
+
+    let results = check(
+        "nobody",
+        json!({ "Site": target(&server, "/api/{}", rule.clone()) }),
+    )
+    .await;
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+
+    let results = check("alice", json!({ "Site": target(&server, "/api/{}", rule) })).await;
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn error_code_field_is_available() {
+    let server = api_server().await;
+    let rule = json!({ "errorType": "json_path", "errorPath": "$.errors[0].code == 404" });
+
+    let results = check(
+        "gone",
+        json!({ "Site": target(&server, "/api/{}", rule.clone()) }),
+    )
+    .await;
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+
+    let results = check("alice", json!({ "Site": target(&server, "/api/{}", rule) })).await;
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn non_json_body_is_unknown() {
+    let server = api_server().await;
+    let rule = json!({ "errorType": "json_path", "errorPath": "$.data.user == null" });
+
+    let results = check(
+        "blocked",
+        json!({ "Site": target(&server, "/api/{}", rule) }),
+    )
+    .await;
+    assert_eq!(results["Site"].status, QueryStatus::Unknown);
+}
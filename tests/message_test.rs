@@ -0,0 +1,294 @@
+mod common;
+
+use common::{check, check_options, check_with, target};
+use serde_json::json;
+use sherlock::query::QueryStatus;
+use sherlock::sherlock_target_manifest::SherlockTargetManifest;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn body_without_error_msg_is_claimed() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<h1>alice's profile</h1>"))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(&server, "/{}", json!({ "errorType": "message", "errorMsg": "User not found" })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn body_with_error_msg_is_available() {
+    let server = MockServer::start().await;
+    Mock::given(path("/nobody"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<p>User not found</p>"))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "nobody",
+        json!({
+            "Site": target(&server, "/{}", json!({ "errorType": "message", "errorMsg": "User not found" })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+}
+
+#[tokio::test]
+async fn any_of_multiple_error_msgs_is_available() {
+    let server = MockServer::start().await;
+    Mock::given(path("/nobody"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<title>Page gone</title>"))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "nobody",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({ "errorType": "message", "errorMsg": ["User not found", "Page gone"] }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+}
+
+#[tokio::test]
+async fn error_status_is_ignored_by_message_detection() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(500).set_body_string("Internal error"))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(&server, "/{}", json!({ "errorType": "message", "errorMsg": "User not found" })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+    assert_eq!(results["Site"].http_status, Some(500));
+}
+
+#[tokio::test]
+async fn redirects_are_followed_for_message_detection() {
+    let server = MockServer::start().await;
+    Mock::given(path("/nobody"))
+        .respond_with(ResponseTemplate::new(302).insert_header("Location", "/missing"))
+        .mount(&server)
+        .await;
+    Mock::given(path("/missing"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("User not found"))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "nobody",
+        json!({
+            "Site": target(&server, "/{}", json!({ "errorType": "message", "errorMsg": "User not found" })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+}
+
+#[tokio::test]
+async fn redirect_limit_is_kept() {
+    let server = MockServer::start().await;
+    // /hop/N redirects to /hop/N-1 and /hop/0 is the profile
+    for hop in 1..=5 {
+        Mock::given(path(format!("/hop/{hop}")))
+            .respond_with(
+                ResponseTemplate::new(302).insert_header("Location", format!("/hop/{}", hop - 1)),
+            )
+            .mount(&server)
+            .await;
+    }
+    Mock::given(path("/hop/0"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<h1>profile</h1>"))
+        .mount(&server)
+        .await;
+
+    let site = |hops: u32| {
+        target(
+            &server,
+            &format!("/hop/{hops}"),
+            json!({ "errorType": "message", "errorMsg": "User not found" }),
+        )
+    };
+    // the same limit as `Policy::limited(5)`, which counts the first URL too
+    let results = check("alice", json!({ "Four": site(4), "Five": site(5) })).await;
+
+    assert_eq!(results["Four"].status, QueryStatus::Claimed);
+    assert_eq!(results["Five"].status, QueryStatus::Unknown);
+}
+
+#[tokio::test]
+async fn regex_error_msg_matches_dynamic_text() {
+    let server = MockServer::start().await;
+    Mock::given(path("/nobody"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Request 8f3a21: no such user"))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "nobody",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({ "errorType": "message", "errorMsg": { "regex": "Request [0-9a-f]+: no such user" } }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+}
+
+#[tokio::test]
+async fn text_and_regex_error_msgs_can_be_mixed() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Welcome back, alice"))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({
+                    "errorType": "message",
+                    "errorMsg": ["User not found", { "regex": "Error \\d{3}" }],
+                }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn claimed_msg_match_is_claimed() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<h1>@alice</h1> 12 followers"))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({
+                    "errorType": "message",
+                    "errorMsg": "User not found",
+                    "claimedMsg": { "regex": "\\d+ followers" },
+                }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn missing_claimed_msg_is_unknown() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Please log in to continue"))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({
+                    "errorType": "message",
+                    "errorMsg": "User not found",
+                    "claimedMsg": { "regex": "\\d+ followers" },
+                }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Unknown);
+    assert!(results["Site"].context.is_some());
+}
+
+#[test]
+fn invalid_regex_fails_manifest_load() {
+    let data = json!({
+        "$schema": "data.schema.json",
+        "Site": {
+            "url": "https://example.com/{}",
+            "urlMain": "https://example.com/",
+            "username_claimed": "alice",
+            "errorType": "message",
+            "errorMsg": { "regex": "(unclosed" },
+        },
+    });
+
+    let error = serde_json::from_value::<SherlockTargetManifest>(data).unwrap_err();
+    assert!(error.to_string().contains("regex parse error"), "{error}");
+}
+
+#[tokio::test]
+async fn error_msg_past_body_limit_is_unknown() {
+    let server = MockServer::start().await;
+    let body = format!("{}User not found", "x".repeat(64));
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(body))
+        .mount(&server)
+        .await;
+
+    let sites = json!({
+        "Site": target(&server, "/{}", json!({ "errorType": "message", "errorMsg": "User not found" })),
+    });
+    let options = sherlock::checker::CheckOptions {
+        max_body_size: 64,
+        ..check_options()
+    };
+
+    assert_eq!(
+        check("alice", sites.clone()).await["Site"].status,
+        QueryStatus::Available
+    );
+    let results = check_with("alice", sites, &options).await;
+    assert_eq!(results["Site"].status, QueryStatus::Unknown);
+    assert_eq!(
+        results["Site"].context.as_deref(),
+        Some("body truncated at 64 bytes")
+    );
+}
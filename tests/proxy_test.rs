@@ -0,0 +1,232 @@
+mod common;
+
+use common::{check_options, check_with};
+use serde_json::json;
+use sherlock::{
+    checker::CheckOptions,
+    proxy_pool::{ProxyPool, Rotation},
+    query::QueryStatus,
+    routing::Routing,
+    tor::{Tor, TorOptions},
+};
+use std::net::TcpListener;
+use std::sync::Arc;
+use wiremock::matchers::path;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn site(url: &str) -> serde_json::Value {
+    json!({
+        "url": url,
+        "urlMain": "http://example.invalid/",
+        "username_claimed": "alice",
+        "errorType": "status_code",
+    })
+}
+
+#[tokio::test]
+async fn requests_go_through_pool_and_dead_proxies_are_failed_over() {
+    // acts as an HTTP proxy: it sees the absolute URL and answers for it
+    let proxy = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&proxy)
+        .await;
+
+    // bind and release a port so that nothing is listening on it
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let dead = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let pool =
+        Arc::new(ProxyPool::new(vec![dead.clone(), proxy.uri()], Rotation::RoundRobin, 1).unwrap());
+    let options = CheckOptions {
+        proxy_pool: Some(Arc::clone(&pool)),
+        retries: 1,
+        ..check_options()
+    };
+
+    // the site sent to the dead proxy is retried on the healthy one
+    let results = check_with(
+        "alice",
+        json!({
+            "A": site("http://a.example.invalid/{}"),
+            "B": site("http://b.example.invalid/{}"),
+        }),
+        &options,
+    )
+    .await;
+    assert!(results.values().all(|r| r.status == QueryStatus::Claimed));
+
+    let stats = pool.stats();
+    assert!(stats[0].1.removed);
+    assert_eq!(stats[0].1.failures, 1);
+    assert!(!stats[1].1.removed);
+    assert_eq!(stats[1].1.requests, 2);
+
+    // only the healthy proxy is used from now on
+    let results = check_with(
+        "alice",
+        json!({
+            "A": site("http://a.example.invalid/{}"),
+            "B": site("http://b.example.invalid/{}"),
+        }),
+        &options,
+    )
+    .await;
+    assert!(results.values().all(|r| r.status == QueryStatus::Claimed));
+}
+
+#[tokio::test]
+async fn sites_that_send_no_request_do_not_count_against_proxies() {
+    let proxy = MockServer::start().await;
+    let pool = Arc::new(ProxyPool::new(vec![proxy.uri()], Rotation::RoundRobin, 1).unwrap());
+    let options = CheckOptions {
+        proxy_pool: Some(Arc::clone(&pool)),
+        ..check_options()
+    };
+
+    let mut unconfigured = site("http://a.example.invalid/{}");
+    unconfigured["headers"] = json!({ "X-Api-Key": "{credential.apiKey}" });
+    let results = check_with("alice", json!({ "Unconfigured": unconfigured }), &options).await;
+
+    assert_eq!(results["Unconfigured"].status, QueryStatus::Unknown);
+    let stats = pool.stats();
+    assert!(!stats[0].1.removed);
+    assert_eq!(stats[0].1.failures, 0);
+}
+
+#[test]
+fn proxies_must_be_allowed_a_failure() {
+    assert!(ProxyPool::new(
+        vec![String::from("http://127.0.0.1:8080")],
+        Rotation::RoundRobin,
+        0
+    )
+    .is_err());
+}
+
+#[tokio::test]
+async fn routing_rules_pick_proxy_or_skip() {
+    let proxy = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&proxy)
+        .await;
+
+    let rules = serde_json::from_value(json!([
+        { "hosts": ["*.corp.invalid"], "route": "skip" },
+        { "sites": ["Routed"], "route": { "proxy": proxy.uri() } },
+    ]))
+    .unwrap();
+    let options = CheckOptions {
+        routing: Some(Arc::new(Routing::new(rules).unwrap())),
+        ..check_options()
+    };
+
+    let results = check_with(
+        "alice",
+        json!({
+            "Routed": site("http://example.invalid/{}"),
+            "Internal": site("http://wiki.corp.invalid/{}"),
+        }),
+        &options,
+    )
+    .await;
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results["Routed"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn tor_errors_are_reported_per_site() {
+    let options = CheckOptions {
+        tor: Some(Arc::new(Tor::new(TorOptions {
+            socks: String::from("not a socks address"),
+            control: None,
+            control_password: None,
+            isolate: false,
+        }))),
+        ..check_options()
+    };
+
+    let results = check_with(
+        "alice",
+        json!({
+            "A": site("http://a.example.invalid/{}"),
+            "B": site("http://b.example.invalid/{}"),
+        }),
+        &options,
+    )
+    .await;
+
+    for result in results.values() {
+        assert_eq!(result.status, QueryStatus::Unknown);
+        assert_eq!(
+            result.context.as_deref(),
+            Some("Invalid Tor SOCKS address 'not a socks address'")
+        );
+    }
+}
+
+#[tokio::test]
+async fn sites_are_unknown_once_every_proxy_is_removed() {
+    // bind and release a port so that nothing is listening on it
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let dead = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let pool = Arc::new(ProxyPool::new(vec![dead], Rotation::RoundRobin, 1).unwrap());
+    let options = CheckOptions {
+        proxy_pool: Some(Arc::clone(&pool)),
+        ..check_options()
+    };
+    let sites = json!({ "A": site("http://a.example.invalid/{}") });
+
+    let results = check_with("alice", sites.clone(), &options).await;
+    assert_eq!(results["A"].status, QueryStatus::Unknown);
+    assert!(pool.stats()[0].1.removed);
+
+    // the scan goes on, reporting the sites it can't check
+    let results = check_with("bob", sites, &options).await;
+    assert_eq!(results["A"].status, QueryStatus::Unknown);
+    assert_eq!(
+        results["A"].context.as_deref(),
+        Some("no healthy proxy left")
+    );
+}
+
+#[tokio::test]
+async fn routing_rules_never_bypass_tor() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    // bind and release a port so that nothing is listening on it
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let socks = listener.local_addr().unwrap().to_string();
+    drop(listener);
+
+    let rules = serde_json::from_value(json!([{ "route": "direct" }])).unwrap();
+    let options = CheckOptions {
+        tor: Some(Arc::new(Tor::new(TorOptions {
+            socks,
+            control: None,
+            control_password: None,
+            isolate: false,
+        }))),
+        routing: Some(Arc::new(Routing::new(rules).unwrap())),
+        ..check_options()
+    };
+
+    let results = check_with(
+        "alice",
+        json!({ "Site": site(&format!("{}/{{}}", server.uri())) }),
+        &options,
+    )
+    .await;
+
+    // the request went to the dead Tor port instead of straight to the server
+    assert_eq!(results["Site"].status, QueryStatus::Unknown);
+}
@@ -0,0 +1,514 @@
+mod common;
+
+use common::{check, check_options, check_with, target};
+use serde_json::json;
+use sherlock::{checker::CheckOptions, query::QueryStatus, user_agent::UserAgents};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use wiremock::matchers::{body_json, body_string, header, header_regex, method, path, query_param};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+#[tokio::test]
+async fn regex_check_rejects_username_without_request() {
+    let server = MockServer::start().await;
+    Mock::given(path("/bad.name"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "bad.name",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({ "errorType": "status_code", "regexCheck": "^[a-z]+$" }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Illegal);
+}
+
+#[tokio::test]
+async fn regex_check_allows_matching_username() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({ "errorType": "status_code", "regexCheck": "^[a-z]+$" }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn url_probe_is_requested_but_profile_url_is_reported() {
+    let server = MockServer::start().await;
+    Mock::given(path("/api/users"))
+        .and(query_param("name", "alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(path("/profile/alice"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/profile/{}",
+                json!({
+                    "errorType": "status_code",
+                    "urlProbe": format!("{}/api/users?name={{}}", server.uri()),
+                }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+    assert_eq!(
+        results["Site"].site_url_user,
+        format!("{}/profile/alice", server.uri())
+    );
+}
+
+#[tokio::test]
+async fn manifest_headers_are_sent() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .and(header("x-api-key", "secret"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({ "errorType": "status_code", "headers": { "x-api-key": "secret" } }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn user_agent_profile_headers_merge_with_manifest_headers() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .and(header("sec-ch-ua-platform", "\"macOS\""))
+        .and(header("accept-language", "de-DE"))
+        .and(header_regex("user-agent", "Chrome/.*Safari"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    let mut user_agents = UserAgents::default();
+    user_agents.fix("chrome-macos").unwrap();
+    let options = CheckOptions {
+        user_agents: Arc::new(user_agents),
+        ..check_options()
+    };
+
+    let results = check_with(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({ "errorType": "status_code", "headers": { "Accept-Language": "de-DE" } }),
+            ),
+        }),
+        &options,
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn request_payload_is_interpolated() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_json(json!({ "query": "user(login: \"alice\")" })))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"data":{"user":{}}}"#))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/{}",
+                json!({
+                    "errorType": "message",
+                    "errorMsg": "\"user\":null",
+                    "urlProbe": format!("{}/graphql", server.uri()),
+                    "request_method": "POST",
+                    "request_payload": { "query": "user(login: \"{}\")" },
+                }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn get_without_payload_sends_no_body() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/alice"))
+        .and(|request: &Request| {
+            request.body.is_empty() && !request.headers.contains_key("content-type")
+        })
+        .respond_with(ResponseTemplate::new(200).set_body_string("<h1>alice</h1>"))
+        .mount(&server)
+        .await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(400))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(&server, "/{}", json!({ "errorType": "message", "errorMsg": "Not found" })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn body_types_are_encoded() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/form"))
+        .and(header("content-type", "application/x-www-form-urlencoded"))
+        .and(body_string("login=alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .and(path("/raw"))
+        .and(header("content-type", "text/plain"))
+        .and(body_string("user:alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(header("content-type", "application/json"))
+        .and(body_json(
+            json!({ "query": "{ user(login: \"alice\") { id } }" }),
+        ))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("OPTIONS"))
+        .and(path("/options"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(wiremock::matchers::any())
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let probe = |path: &str, fields: serde_json::Value| {
+        let mut fields = fields;
+        fields["errorType"] = json!("status_code");
+        fields["urlProbe"] = json!(format!("{}{path}", server.uri()));
+        target(&server, "/{}", fields)
+    };
+
+    let results = check(
+        "alice",
+        json!({
+            "Form": probe("/form", json!({
+                "request_method": "POST",
+                "request_body_type": "form",
+                "request_payload": { "login": "{}" },
+            })),
+            "Raw": probe("/raw", json!({
+                "request_method": "PATCH",
+                "request_body_type": "raw",
+                "request_payload": "user:{}",
+                "headers": { "Content-Type": "text/plain" },
+            })),
+            "GraphQL": probe("/graphql", json!({
+                "request_method": "POST",
+                "request_body_type": "graphql",
+                "request_payload": "{ user(login: \"{}\") { id } }",
+            })),
+            "Options": probe("/options", json!({ "request_method": "OPTIONS" })),
+            "Invalid": probe("/form", json!({
+                "request_method": "POST",
+                "request_body_type": "form",
+                "request_payload": "login=alice",
+            })),
+        }),
+    )
+    .await;
+
+    for site in ["Form", "Raw", "GraphQL", "Options"] {
+        assert_eq!(results[site].status, QueryStatus::Claimed, "{site}");
+    }
+    assert_eq!(results["Invalid"].status, QueryStatus::Unknown);
+    assert_eq!(
+        results["Invalid"].context.as_deref(),
+        Some("a form body must be a JSON object")
+    );
+}
+
+#[tokio::test]
+async fn spaces_in_username_are_encoded() {
+    let server = MockServer::start().await;
+    Mock::given(path("/john%20doe"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "john doe",
+        json!({ "Site": target(&server, "/{}", json!({ "errorType": "status_code" })) }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn waf_fingerprint_is_reported() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"<script>{return l.onPageView}}),Object.defineProperty(r,"perimeterxIdentifiers",{enumerable:</script>"#,
+        ))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(&server, "/{}", json!({ "errorType": "message", "errorMsg": "User not found" })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Waf);
+}
+
+#[tokio::test]
+async fn connection_failure_is_unknown() {
+    // bind and release a port so that nothing is listening on it
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": {
+                "url": format!("{uri}/{{}}"),
+                "urlMain": uri,
+                "username_claimed": "alice",
+                "errorType": "status_code",
+            },
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Unknown);
+    assert!(results["Site"].context.is_some());
+}
+
+#[tokio::test]
+async fn broken_body_is_unknown_and_scan_completes() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<h1>alice</h1>"))
+        .mount(&server)
+        .await;
+
+    // promise a longer body than is sent, then hang up
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0; 4096];
+        let _ = stream.read(&mut request);
+        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\nshort");
+    });
+
+    let results = check(
+        "alice",
+        json!({
+            "Broken": {
+                "url": format!("{uri}/{{}}"),
+                "urlMain": uri,
+                "username_claimed": "alice",
+                "errorType": "message",
+                "errorMsg": "User not found",
+            },
+            "Site": target(&server, "/{}", json!({ "errorType": "status_code" })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Broken"].status, QueryStatus::Unknown);
+    assert!(results["Broken"]
+        .context
+        .as_deref()
+        .unwrap()
+        .starts_with("failed to read response body"));
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn every_site_gets_a_result() {
+    let server = MockServer::start().await;
+    Mock::given(path("/a/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(path("/b/alice"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "A": target(&server, "/a/{}", json!({ "errorType": "status_code" })),
+            "B": target(&server, "/b/{}", json!({ "errorType": "status_code" })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results["A"].status, QueryStatus::Claimed);
+    assert_eq!(results["B"].status, QueryStatus::Available);
+}
+
+/// Times out the first request to each path and answers the rest right away.
+struct FlakyOnce(std::sync::Mutex<std::collections::HashSet<String>>);
+
+impl wiremock::Respond for FlakyOnce {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let first = self
+            .0
+            .lock()
+            .unwrap()
+            .insert(request.url.path().to_string());
+        match first && request.url.path().starts_with("/flaky") {
+            true => ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(5)),
+            false => ResponseTemplate::new(200).set_body_string("x".repeat(512)),
+        }
+    }
+}
+
+#[tokio::test]
+async fn failed_requests_are_retried_with_lower_confidence() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(FlakyOnce(Default::default()))
+        .mount(&server)
+        .await;
+
+    let fields = json!({ "errorType": "message", "errorMsg": "Not found" });
+    let sites = json!({
+        "Flaky": target(&server, "/flaky/{}", fields.clone()),
+        "Steady": target(&server, "/steady/{}", fields),
+    });
+    let options = CheckOptions {
+        timeout: std::time::Duration::from_millis(300),
+        ..check_options()
+    };
+
+    let results = check_with("alice", sites.clone(), &options).await;
+    assert_eq!(results["Flaky"].status, QueryStatus::Unknown);
+
+    server.reset().await;
+    Mock::given(method("GET"))
+        .respond_with(FlakyOnce(Default::default()))
+        .mount(&server)
+        .await;
+    let options = CheckOptions {
+        retries: 1,
+        ..options
+    };
+    let results = check_with("alice", sites, &options).await;
+    assert_eq!(results["Flaky"].status, QueryStatus::Claimed);
+    assert_eq!(results["Steady"].status, QueryStatus::Claimed);
+    assert!(results["Flaky"].confidence < results["Steady"].confidence);
+}
+
+#[tokio::test]
+async fn requests_that_would_fail_again_are_not_retried() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    // a header value with a newline can never be sent
+    let sites = json!({
+        "Broken": target(&server, "/{}", json!({
+            "errorType": "status_code",
+            "headers": { "X-Broken": "a\nb" },
+        })),
+    });
+    let options = CheckOptions {
+        retries: 3,
+        ..check_options()
+    };
+
+    let results = check_with("alice", sites, &options).await;
+    assert_eq!(results["Broken"].status, QueryStatus::Unknown);
+    assert_eq!(results["Broken"].context.as_deref(), Some("Request error"));
+    // no retry delay was waited for
+    assert!(results["Broken"].query_time < std::time::Duration::from_millis(500));
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use common::{check, target};
+use serde_json::json;
+use sherlock::query::QueryStatus;
+use wiremock::matchers::path;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn direct_success_is_claimed() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(&server, "/{}", json!({ "errorType": "response_url", "errorUrl": "/error" })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn redirect_is_not_followed_and_is_available() {
+    let server = MockServer::start().await;
+    Mock::given(path("/nobody"))
+        .respond_with(ResponseTemplate::new(302).insert_header("Location", "/error"))
+        .mount(&server)
+        .await;
+    Mock::given(path("/error"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "nobody",
+        json!({
+            "Site": target(&server, "/{}", json!({ "errorType": "response_url", "errorUrl": "/error" })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+    assert_eq!(results["Site"].http_status, Some(302));
+}
@@ -1 +1,168 @@
+mod common;
 
+use common::{check, target};
+use serde_json::json;
+use sherlock::query::QueryStatus;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn success_status_is_claimed() {
+    let server = MockServer::start().await;
+    Mock::given(path("/u/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({ "Site": target(&server, "/u/{}", json!({ "errorType": "status_code" })) }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+    assert_eq!(results["Site"].http_status, Some(200));
+}
+
+#[tokio::test]
+async fn error_status_without_error_code_is_available() {
+    let server = MockServer::start().await;
+    Mock::given(path("/u/nobody"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "nobody",
+        json!({ "Site": target(&server, "/u/{}", json!({ "errorType": "status_code" })) }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+}
+
+#[tokio::test]
+async fn redirect_status_without_error_code_is_claimed() {
+    let server = MockServer::start().await;
+    Mock::given(path("/u/alice"))
+        .respond_with(ResponseTemplate::new(304))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({ "Site": target(&server, "/u/{}", json!({ "errorType": "status_code" })) }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn single_error_code_is_available() {
+    let server = MockServer::start().await;
+    Mock::given(path("/u/nobody"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "nobody",
+        json!({
+            "Site": target(&server, "/u/{}", json!({ "errorType": "status_code", "errorCode": 403 })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+}
+
+#[tokio::test]
+async fn status_outside_error_codes_is_claimed() {
+    let server = MockServer::start().await;
+    Mock::given(path("/u/alice"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/u/{}",
+                json!({ "errorType": "status_code", "errorCode": [403, 410] }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn status_in_error_code_list_is_available() {
+    let server = MockServer::start().await;
+    Mock::given(path("/u/nobody"))
+        .respond_with(ResponseTemplate::new(410))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "nobody",
+        json!({
+            "Site": target(
+                &server,
+                "/u/{}",
+                json!({ "errorType": "status_code", "errorCode": [403, 410] }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Available);
+}
+
+#[tokio::test]
+async fn status_code_sites_default_to_head() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .and(path("/u/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({ "Site": target(&server, "/u/{}", json!({ "errorType": "status_code" })) }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn request_method_overrides_head() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/u/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(
+                &server,
+                "/u/{}",
+                json!({ "errorType": "status_code", "request_method": "GET" }),
+            ),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::{check, target};
+use serde_json::json;
+use sherlock::query::QueryStatus;
+use wiremock::matchers::{body_json, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn tokens_from_steps_are_used_by_probe() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("set-cookie", "session=s1; Path=/")
+                .set_body_string(r#"<meta name="csrf" content="c5rf">"#),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/guest"))
+        .and(header("x-csrf", "c5rf"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "guest_token": "g1" })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/lookup"))
+        .and(header("cookie", "session=s1"))
+        .and(header("x-guest-token", "g1"))
+        .and(body_json(json!({ "login": "alice", "csrf": "c5rf" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "user": { "id": 1 } })))
+        .mount(&server)
+        .await;
+    Mock::given(path("/lookup"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(&server, "/{}", json!({
+                "steps": [
+                    {
+                        "url": format!("{}/", server.uri()),
+                        "extract": { "csrf": { "css": "meta[name=csrf]", "attr": "content" } },
+                    },
+                    {
+                        "url": format!("{}/guest", server.uri()),
+                        "request_method": "POST",
+                        "headers": { "X-Csrf": "{token.csrf}" },
+                        "extract": { "guest": { "json": "$.guest_token" } },
+                    },
+                ],
+                "urlProbe": format!("{}/lookup", server.uri()),
+                "request_method": "POST",
+                "headers": { "X-Guest-Token": "{token.guest}" },
+                "request_payload": { "login": "{}", "csrf": "{token.csrf}" },
+                "errorType": "json_path",
+                "errorPath": "$.user == null",
+            })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn missing_token_is_unknown() {
+    let server = MockServer::start().await;
+    Mock::given(path("/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("no token here"))
+        .mount(&server)
+        .await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(&server, "/{}", json!({
+                "steps": [{
+                    "url": format!("{}/token", server.uri()),
+                    "extract": { "guest": { "regex": "token=(\\w+)" } },
+                }],
+                "errorType": "status_code",
+            })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Unknown);
+    assert_eq!(
+        results["Site"].context.as_deref(),
+        Some("Step 1 failed: no value for token 'guest'")
+    );
+}
+
+#[tokio::test]
+async fn token_with_newline_is_unknown() {
+    let server = MockServer::start().await;
+    Mock::given(path("/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("token=abc\ndef;"))
+        .mount(&server)
+        .await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(&server, "/{}", json!({
+                "steps": [{
+                    "url": format!("{}/token", server.uri()),
+                    "extract": { "guest": { "regex": "token=([^;]+);" } },
+                }],
+                "headers": { "X-Guest-Token": "{token.guest}" },
+                "errorType": "status_code",
+            })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Unknown);
+    assert_eq!(
+        results["Site"].context.as_deref(),
+        Some("Step 1 failed: token 'guest' is not a valid header value")
+    );
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::{check_options, check_with, target};
+use serde_json::json;
+use sherlock::{
+    checker::CheckOptions,
+    query::QueryStatus,
+    transport::{HttpVersion, Transport},
+};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use wiremock::matchers::path;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn options(http_version: HttpVersion) -> CheckOptions {
+    CheckOptions {
+        transport: Arc::new(Transport::new(http_version)),
+        ..check_options()
+    }
+}
+
+/// A server that only speaks HTTP/1.1 and answers every request with 200.
+async fn http1_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0; 4096];
+                if stream.read(&mut buf).await.is_ok() {
+                    let _ = stream
+                        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                        .await;
+                }
+            });
+        }
+    });
+
+    format!("http://{address}")
+}
+
+#[tokio::test]
+async fn http_version_is_applied() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let http1 = http1_server().await;
+
+    let sites = || {
+        json!({
+            "Both": target(&server, "/{}", json!({ "errorType": "status_code" })),
+            "Http1Only": {
+                "url": format!("{http1}/{{}}"),
+                "urlMain": http1,
+                "username_claimed": "alice",
+                "errorType": "status_code",
+            },
+        })
+    };
+
+    let results = check_with("alice", sites(), &options(HttpVersion::Http1)).await;
+    assert_eq!(results["Both"].status, QueryStatus::Claimed);
+    assert_eq!(results["Http1Only"].status, QueryStatus::Claimed);
+
+    // HTTP/2 is spoken without negotiating, which an HTTP/1.1 server can't answer
+    let results = check_with("alice", sites(), &options(HttpVersion::Http2)).await;
+    assert_eq!(results["Both"].status, QueryStatus::Claimed);
+    assert_eq!(results["Http1Only"].status, QueryStatus::Unknown);
+}
+
+#[tokio::test]
+async fn http3_is_never_sent_around_a_proxy() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let options = CheckOptions {
+        proxy: Some(Arc::from(server.uri())),
+        ..options(HttpVersion::Http3)
+    };
+    let sites = json!({ "Site": target(&server, "/{}", json!({ "errorType": "status_code" })) });
+    let results = check_with("alice", sites, &options).await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Unknown);
+}
+
+#[cfg(not(feature = "http3"))]
+#[tokio::test]
+async fn http3_needs_the_feature() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let sites = json!({ "Site": target(&server, "/{}", json!({ "errorType": "status_code" })) });
+    let results = check_with("alice", sites, &options(HttpVersion::Http3)).await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Unknown);
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use common::{check, check_options, check_with, target};
+use serde_json::json;
+use sherlock::{query::QueryStatus, waf::WafDatabase};
+use std::sync::Arc;
+use wiremock::matchers::path;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn challenge_header_reports_vendor() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(
+            ResponseTemplate::new(403)
+                .insert_header("cf-mitigated", "challenge")
+                .set_body_string("<h1>alice</h1>"),
+        )
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(&server, "/{}", json!({ "errorType": "status_code" })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Waf);
+    assert_eq!(results["Site"].context.as_deref(), Some("Cloudflare"));
+}
+
+#[tokio::test]
+async fn challenge_body_is_seen_on_sites_without_body_detection() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(ResponseTemplate::new(403).set_body_string(
+            "<html><script src=\"https://captcha.px-cdn.net/PXabc/captcha.js\"></script></html>",
+        ))
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Status": target(&server, "/{}", json!({ "errorType": "status_code", "request_method": "GET" })),
+            "Redirect": target(&server, "/{}", json!({
+                "errorType": "response_url",
+                "errorUrl": format!("{}/missing", server.uri()),
+            })),
+        }),
+    )
+    .await;
+
+    for site in ["Status", "Redirect"] {
+        assert_eq!(results[site].status, QueryStatus::Waf, "{site}");
+        assert_eq!(
+            results[site].context.as_deref(),
+            Some("PerimeterX"),
+            "{site}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn vendor_header_alone_is_not_a_block() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("server", "cloudflare")
+                .set_body_string("<h1>alice</h1>"),
+        )
+        .mount(&server)
+        .await;
+
+    let results = check(
+        "alice",
+        json!({
+            "Site": target(&server, "/{}", json!({ "errorType": "status_code" })),
+        }),
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Claimed);
+}
+
+#[tokio::test]
+async fn fingerprint_file_adds_vendor() {
+    let server = MockServer::start().await;
+    Mock::given(path("/alice"))
+        .respond_with(
+            ResponseTemplate::new(429)
+                .insert_header("x-shield", "blocked")
+                .set_body_string("Slow down"),
+        )
+        .mount(&server)
+        .await;
+
+    let path = std::env::temp_dir().join(format!("waf-fingerprints-{}.json", std::process::id()));
+    std::fs::write(
+        &path,
+        json!({
+            "fingerprints": [{
+                "vendor": "Shield",
+                "statusCode": 429,
+                "headers": { "x-shield": { "regex": "^block" } }
+            }]
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let mut waf = WafDatabase::default();
+    let loaded = waf.load(path.to_str().unwrap());
+    std::fs::remove_file(&path).unwrap();
+    loaded.unwrap();
+
+    let options = sherlock::checker::CheckOptions {
+        waf: Arc::new(waf),
+        ..check_options()
+    };
+    let results = check_with(
+        "alice",
+        json!({
+            "Site": target(&server, "/{}", json!({ "errorType": "status_code" })),
+        }),
+        &options,
+    )
+    .await;
+
+    assert_eq!(results["Site"].status, QueryStatus::Waf);
+    assert_eq!(results["Site"].context.as_deref(), Some("Shield"));
+}